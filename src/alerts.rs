@@ -1,15 +1,22 @@
+use crate::collect_info;
 use crate::db::Database;
-use crate::models::{Alert, AlertVar, NotificationConfig, NotificationMethod, WebHookNotif};
-use log::{debug, error, info, trace};
-use reqwest::Client;
-use reqwest::tls::Certificate;
-use rusqlite::params;
-use std::collections::HashMap;
+use crate::hub::Hub;
+use crate::models::{
+    ALERT_VARIABLES, Alert, AlertCheck, AlertCondition, AlertEvent, AlertStateChange, AlertVar,
+    DockerContainer, NotificationMethod, ProcessInfo, QueuedNotification, Silence,
+};
+use crate::notifications::AlertContext;
+use crate::silences;
+use chrono::TimeZone;
+use log::{debug, error, info, trace, warn};
+use regex::Regex;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
 use tokio::time::{Duration, interval};
 
 /// Main function to check alerts and send notifications
-pub async fn check_alerts(db_path: &str) {
+pub async fn check_alerts(db_path: &str, hub: Arc<Hub>, interval_secs: u64) {
     let db = match Database::new(db_path) {
         Ok(db) => Arc::new(db),
         Err(e) => {
@@ -18,9 +25,14 @@ pub async fn check_alerts(db_path: &str) {
         }
     };
 
+    let mut history = SampleHistory::default();
+    let mut sys = System::new();
+
     // Run alert checking loop
-    let mut interval_timer = interval(Duration::from_secs(60)); // Check every minute
-    tokio::time::sleep(Duration::from_secs(70)).await;
+    let mut interval_timer = interval(Duration::from_secs(interval_secs));
+    // Checks land an interval apart give or take, notification delays round to the closest one
+    let slack = interval_secs as i64 / 2;
+    tokio::time::sleep(Duration::from_secs(interval_secs + 10)).await;
     loop {
         debug!("Checking alerts");
         interval_timer.tick().await;
@@ -45,11 +57,35 @@ pub async fn check_alerts(db_path: &str) {
             }
         };
 
+        // Alerts are still evaluated without silences, just never muted
+        let silences = db.get_silences().unwrap_or_else(|e| {
+            error!("Failed to get silences: {}", e);
+            Vec::new()
+        });
+
         let method_map: HashMap<String, NotificationMethod> = notification_methods
             .into_iter()
             .map(|m| (m.id.clone(), m))
             .collect();
 
+        // Docker and process data isn't stored in the database, sample it while alerts need it
+        let sampled: Vec<(&AlertVar, f64)> = alerts
+            .iter()
+            .filter(|a| a.enabled)
+            .flat_map(alert_vars)
+            .filter(|(var, _)| SAMPLED_CATEGORIES.contains(&var.cat.as_str()))
+            .collect();
+        if let Some(window) = sampled.iter().map(|(_, window)| *window).reduce(f64::max) {
+            let vars: Vec<&AlertVar> = sampled.iter().map(|(var, _)| *var).collect();
+            let values = take_samples(&vars, &mut sys).await;
+            let keep_secs = (window * 60.0).ceil() as i64;
+            history.record(chrono::Utc::now().timestamp(), values, keep_secs);
+        }
+
+        // Notifications of this check, queued together once all alerts are checked
+        let cycle = chrono::Utc::now().timestamp();
+        let mut queue = Vec::new();
+
         // Process each alert
         for mut alert in alerts {
             trace!("Checking alert: {:?}", alert);
@@ -58,277 +94,902 @@ pub async fn check_alerts(db_path: &str) {
             }
 
             // Check if alert condition is met
-            let is_firing = match check_alert_condition(&db, &alert) {
-                Ok(firing) => firing,
+            let evaluation = match check_alert_condition(&db, &history, &alert) {
+                Ok(evaluation) => evaluation,
                 Err(e) => {
                     error!("Failed to check alert condition for {:?}: {}", alert, e);
                     continue;
                 }
             };
+            let is_firing = evaluation.firing;
             trace!("Alert firing: {}", is_firing);
 
+            let now = chrono::Utc::now().timestamp();
+            let silence = silences::find_active(&silences, &alert, now);
+
             // If alert state has changed, update it in the database
             if is_firing != alert.firing {
                 alert.firing = is_firing;
-                if let Err(e) = update_alert_state(&db, &alert) {
-                    error!("Failed to update alert state: {}", e);
-                }
-
                 if is_firing {
                     info!("Alert fired: {:?}", alert);
-                    let notification_message = format_alert_message(&alert, true);
-
-                    // Send notifications to all configured methods for this alert
-                    for method_id in &alert.notif_methods {
-                        if let Some(method) = method_map.get(method_id)
-                            && method.enabled
-                            && let Err(e) = send_notification(method, &notification_message).await
-                        {
-                            error!("Failed to send notification: {}", e);
-                        }
-                    }
                 } else {
-                    // send the relief
                     info!("Alert relief: {:?}", alert);
-                    let notification_message = format_alert_message(&alert, false);
-
-                    // Send notifications to all configured methods for this alert
-                    for method_id in &alert.notif_methods {
-                        if let Some(method) = method_map.get(method_id)
-                            && method.enabled
-                            && let Err(e) = send_notification(method, &notification_message).await
-                        {
-                            error!("Failed to send notification: {}", e);
-                        }
-                    }
+                }
+
+                // Resolutions also reach the methods the alert was escalated to
+                let recipients = notified_methods(&alert);
+                let fired_at = if is_firing { Some(now) } else { alert.fired_at };
+                if is_firing {
+                    alert.fired_at = Some(now);
+                    alert.last_notified = Some(now);
+                } else {
+                    alert.fired_at = None;
+                    alert.last_notified = None;
+                    alert.escalation_level = 0;
+                }
+                if let Err(e) = update_alert_state(&db, &alert) {
+                    error!("Failed to update alert state: {}", e);
+                }
+
+                let notification_message = format_alert_message(&alert, is_firing);
+                hub.publish_alert_change(AlertStateChange {
+                    t: now,
+                    alert_id: alert.id.clone(),
+                    firing: is_firing,
+                    message: notification_message.clone(),
+                });
+                let transition = if is_firing { "fired" } else { "resolved" };
+                let notification = Notification {
+                    transition,
+                    message: notification_message,
+                    value: evaluation.value,
+                    fired_at,
+                    silence,
+                };
+                queue.extend(notify(&db, &method_map, &alert, &notification, &recipients));
+            } else if alert.firing {
+                let fired_at = *alert.fired_at.get_or_insert(now);
+                let last_notified = *alert.last_notified.get_or_insert(now);
+                let firing_for = (now - fired_at + slack) / 60;
+                let mut notifications = Vec::new();
+
+                while let Some(escalation) = alert.escalations.get(alert.escalation_level as usize)
+                    && firing_for >= escalation.after
+                {
+                    info!(
+                        "Alert {} escalated after {} min",
+                        alert.id, escalation.after
+                    );
+                    alert.escalation_level += 1;
+                    let notification = Notification {
+                        transition: "escalated",
+                        message: format_alert_message(&alert, true),
+                        value: evaluation.value,
+                        fired_at: Some(fired_at),
+                        silence,
+                    };
+                    notifications.push((notification, escalation.notif_methods.clone()));
+                }
+
+                if let Some(interval) = alert.renotify_interval
+                    && (now - last_notified + slack) / 60 >= interval
+                {
+                    alert.last_notified = Some(now);
+                    let notification = Notification {
+                        transition: "renotified",
+                        message: format!(
+                            "{} - still firing after {} min",
+                            format_alert_message(&alert, true),
+                            firing_for
+                        ),
+                        value: evaluation.value,
+                        fired_at: Some(fired_at),
+                        silence,
+                    };
+                    notifications.push((notification, notified_methods(&alert)));
+                }
+
+                // Always saved, alerts firing from before these fields existed get them here
+                if let Err(e) = update_alert_state(&db, &alert) {
+                    error!("Failed to update alert state: {}", e);
+                }
+                for (notification, recipients) in notifications {
+                    queue.extend(notify(&db, &method_map, &alert, &notification, &recipients));
                 }
             }
         }
+
+        queue_notifications(&db, &hub, &method_map, queue, cycle);
     }
 }
 
-fn get_alerts(db: &Database) -> Result<Vec<Alert>, String> {
-    let alerts_json = match db.get_kv_str("alerts") {
-        Ok(Some(json)) => json,
-        Ok(None) => return Ok(Vec::new()),
-        Err(e) => return Err(format!("Database error: {}", e)),
-    };
+/// Notification about an alert, along with what's recorded in its history
+struct Notification<'a> {
+    transition: &'static str,
+    message: String,
+    value: Option<f64>,
+    fired_at: Option<i64>, // Start of the firing period, also for resolutions
+    silence: Option<&'a Silence>,
+}
 
-    serde_json::from_str::<Vec<Alert>>(&alerts_json)
-        .map_err(|e| format!("Failed to parse alerts JSON: {}", e))
+/// Methods of an alert along with the ones it has been escalated to
+fn notified_methods(alert: &Alert) -> Vec<String> {
+    let mut methods = alert.notif_methods.clone();
+    for escalation in alert
+        .escalations
+        .iter()
+        .take(alert.escalation_level as usize)
+    {
+        for method in &escalation.notif_methods {
+            if !methods.contains(method) {
+                methods.push(method.clone());
+            }
+        }
+    }
+    methods
 }
 
-fn get_notification_methods(db: &Database) -> Result<Vec<NotificationMethod>, String> {
-    let methods_json = match db.get_kv_str("notification_methods") {
-        Ok(Some(json)) => json,
-        Ok(None) => return Ok(Vec::new()),
-        Err(e) => return Err(format!("Database error: {}", e)),
+/// Record an alert event and return its notifications for the given methods, unless silenced
+fn notify(
+    db: &Database,
+    method_map: &HashMap<String, NotificationMethod>,
+    alert: &Alert,
+    notification: &Notification<'_>,
+    method_ids: &[String],
+) -> Vec<QueuedNotification> {
+    let event_id = match db.insert_alert_event(&AlertEvent {
+        id: 0,
+        alert_id: alert.id.clone(),
+        t: chrono::Utc::now().timestamp(),
+        transition: notification.transition.to_string(),
+        value: notification.value,
+        threshold: alert.threshold,
+        message: notification.message.clone(),
+        silenced_by: notification.silence.map(|s| s.id.clone()),
+        deliveries: Vec::new(),
+    }) {
+        Ok(id) => Some(id),
+        Err(e) => {
+            error!("Failed to record alert event: {}", e);
+            None
+        }
     };
 
-    serde_json::from_str::<Vec<NotificationMethod>>(&methods_json)
-        .map_err(|e| format!("Failed to parse notification methods JSON: {}", e))
+    if let Some(silence) = notification.silence {
+        info!(
+            "Notifications of alert {} silenced by {}",
+            alert.id, silence.id
+        );
+        return Vec::new();
+    }
+
+    let context = alert_context(alert, notification);
+    let context = match serde_json::to_value(&context) {
+        Ok(context) => context,
+        Err(e) => {
+            error!("Failed to serialize alert context: {}", e);
+            return Vec::new();
+        }
+    };
+    let now = chrono::Utc::now().timestamp();
+    method_ids
+        .iter()
+        .filter(|id| method_map.get(*id).is_some_and(|m| m.enabled))
+        .map(|method_id| QueuedNotification {
+            id: 0,
+            event_id,
+            method_id: method_id.clone(),
+            template: alert.template.clone(),
+            context: context.clone(),
+            attempts: 0,
+            next_attempt: now,
+            batch: None,
+        })
+        .collect()
 }
 
-/// Check if an alert condition is met consistently across the entire time window
-fn check_alert_condition(db: &Database, alert: &Alert) -> Result<bool, String> {
-    let time_window_secs = alert.time_window * 60;
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map_err(|e| format!("Time error: {}", e))?
-        .as_secs();
+/// Queue the notifications of a check. Methods that group notifications get the ones
+/// of the check as a batch, methods with a digest add them to the one collecting them.
+fn queue_notifications(
+    db: &Database,
+    hub: &Hub,
+    method_map: &HashMap<String, NotificationMethod>,
+    mut queue: Vec<QueuedNotification>,
+    cycle: i64,
+) {
+    if queue.is_empty() {
+        return;
+    }
+    let now = chrono::Utc::now().timestamp();
+    let mut digests: HashMap<String, (String, i64)> = HashMap::new();
+    for notification in &mut queue {
+        let Some(method) = method_map.get(&notification.method_id) else {
+            continue;
+        };
+        if let Some(interval) = method.digest_interval {
+            let (batch, send_at) = digests.entry(method.id.clone()).or_insert_with(|| {
+                db.open_digest(&method.id, now)
+                    .unwrap_or_else(|e| {
+                        error!("Failed to get the digest of {}: {}", method.name, e);
+                        None
+                    })
+                    .unwrap_or_else(|| (format!("digest:{}", now), now + i64::from(interval) * 60))
+            });
+            notification.batch = Some(batch.clone());
+            notification.next_attempt = *send_at;
+        } else if method.grouping {
+            notification.batch = Some(format!("check:{}", cycle));
+        }
+    }
 
-    let start_time = now - time_window_secs as u64;
+    if let Err(e) = db.enqueue_notifications(&queue) {
+        error!("Failed to queue notifications: {}", e);
+    }
+    hub.notifications.notify_one();
+}
 
-    // Choose the appropriate table based on the time window
-    let table_suffix = if time_window_secs <= 7200 {
-        "m" // Use minute-level data for windows <= 2 hours
-    } else {
-        "h" // Use hour-level data for longer windows
+/// Whether an edit changes when an alert fires, so its state no longer applies.
+/// Disabling it counts too, as disabled alerts aren't checked and would stay firing.
+pub fn condition_changed(stored: &Alert, edited: &Alert) -> bool {
+    let condition = |alert: &Alert| {
+        serde_json::json!([
+            alert.var,
+            alert.kind,
+            alert.threshold,
+            alert.recovery_threshold,
+            alert.horizon,
+            alert.operator,
+            alert.time_window,
+            alert.resolution,
+            alert.condition,
+        ])
     };
+    condition(stored) != condition(edited) || (stored.enabled && !edited.enabled)
+}
 
-    // Build query
-    let conn = db.conn.lock().unwrap();
+/// Resolve a firing alert that was edited, notifying everyone its firing reached,
+/// so the alert loop starts over with its new condition
+pub fn resolve_edited_alert(db: &Database, hub: &Hub, mut alert: Alert) -> Result<(), String> {
+    if !alert.firing {
+        return Ok(());
+    }
+    let method_map: HashMap<String, NotificationMethod> = get_notification_methods(db)?
+        .into_iter()
+        .map(|m| (m.id.clone(), m))
+        .collect();
+    let silences = db.get_silences().unwrap_or_else(|e| {
+        error!("Failed to get silences: {}", e);
+        Vec::new()
+    });
 
-    let (agg_function, comparison_op) = match alert.operator.as_str() {
-        ">" => ("MIN", ">"), // If MIN value > threshold, then ALL values > threshold
-        "<" => ("MAX", "<"), // If MAX value < threshold, then ALL values < threshold
-        _ => return Err(format!("Unknown operator: {}", alert.operator)),
+    let now = chrono::Utc::now().timestamp();
+    let recipients = notified_methods(&alert);
+    let fired_at = alert.fired_at;
+    alert.firing = false;
+    alert.fired_at = None;
+    alert.last_notified = None;
+    alert.escalation_level = 0;
+    update_alert_state(db, &alert)?;
+    info!("Alert {} resolved by an edit", alert.id);
+
+    let message = format!("{} - alert edited", format_alert_message(&alert, false));
+    hub.publish_alert_change(AlertStateChange {
+        t: now,
+        alert_id: alert.id.clone(),
+        firing: false,
+        message: message.clone(),
+    });
+    let notification = Notification {
+        transition: "resolved",
+        message,
+        value: None,
+        fired_at,
+        silence: silences::find_active(&silences, &alert, now),
     };
+    let queue = notify(db, &method_map, &alert, &notification, &recipients);
+    queue_notifications(db, hub, &method_map, queue, now);
+    Ok(())
+}
 
-    let query_result = match alert.var.cat.as_str() {
-        "sys" => {
-            // System metrics are in general_* tables
-            let query = format!(
-                "SELECT {}({}) FROM general_{} WHERE timestamp >= ?",
-                agg_function, alert.var.var, table_suffix
-            );
-            conn.query_row(&query, params![start_time as i64], |row| {
-                row.get::<_, f64>(0)
-            })
+pub fn get_alerts(db: &Database) -> Result<Vec<Alert>, String> {
+    db.get_alerts()
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+fn get_notification_methods(db: &Database) -> Result<Vec<NotificationMethod>, String> {
+    db.get_notification_methods()
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Operators an alert condition can use
+pub const ALERT_OPERATORS: [&str; 6] = [">", ">=", "<", "<=", "==", "!="];
+
+/// Outcome of checking an alert condition
+struct Evaluation {
+    firing: bool,
+    value: Option<f64>, // Aggregate compared to the threshold, None without data
+}
+
+/// Extremes of a metric over the time window of an alert
+#[derive(Debug, Clone, Copy)]
+struct WindowStats {
+    min: f64,
+    max: f64,
+    count: i64,
+}
+
+/// Categories sampled by the alert loop, as their data isn't stored in the database
+const SAMPLED_CATEGORIES: [&str; 2] = ["docker", "proc"];
+
+/// Sampled variable as (category, resource, variable)
+type SampleKey = (String, String, String);
+
+/// Values of the sampled categories taken by the alert loop, oldest first
+#[derive(Default)]
+struct SampleHistory {
+    samples: VecDeque<(i64, HashMap<SampleKey, f64>)>,
+}
+
+impl SampleHistory {
+    /// Add a sample, keeping one more than needed to cover `keep_secs`
+    fn record(&mut self, t: i64, values: HashMap<SampleKey, f64>, keep_secs: i64) {
+        self.samples.push_back((t, values));
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(second, _)| *second <= t - keep_secs)
+        {
+            self.samples.pop_front();
         }
-        "net" | "disk" => {
-            // Network or disk metrics need to filter by resource name
-            let query = format!(
-                "SELECT {}({}) FROM {}_{} WHERE timestamp >= ? AND name = ?",
-                agg_function, alert.var.var, alert.var.cat, table_suffix
-            );
-            conn.query_row(&query, params![start_time as i64, alert.var.resrc], |row| {
-                row.get::<_, f64>(0)
-            })
+    }
+
+    /// Values of a variable since `start`, and the last value before it
+    fn values(&self, var: &AlertVar, start: i64) -> (Option<f64>, Vec<f64>) {
+        let key = (var.cat.clone(), var.resrc.clone(), var.var.clone());
+        let mut before = None;
+        let mut values = Vec::new();
+        for (t, sample) in &self.samples {
+            match sample.get(&key) {
+                Some(&value) if *t >= start => values.push(value),
+                Some(&value) => before = Some(value),
+                None => {}
+            }
         }
-        _ => return Err(format!("Unknown category: {}", alert.var.cat)),
-    };
+        (before, values)
+    }
+}
 
-    let agg_value = match query_result {
-        Ok(value) => value,
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            // No data in the time window
-            return Ok(false); // firing=false if there's no data
+/// Variables used by an alert, with their time windows in minutes
+pub fn alert_vars(alert: &Alert) -> Vec<(&AlertVar, f64)> {
+    fn collect<'a>(condition: &'a AlertCondition, vars: &mut Vec<(&'a AlertVar, f64)>) {
+        match condition {
+            AlertCondition::And { conditions } | AlertCondition::Or { conditions } => conditions
+                .iter()
+                .for_each(|condition| collect(condition, vars)),
+            AlertCondition::Not { condition } => collect(condition, vars),
+            AlertCondition::Metric(check) => vars.push((&check.var, check.time_window)),
         }
-        Err(e) => return Err(format!("Database query error: {}", e)),
-    };
+    }
+    let mut vars = Vec::new();
+    match &alert.condition {
+        Some(condition) => collect(condition, &mut vars),
+        None => vars.push((&alert.var, alert.time_window)),
+    }
+    vars
+}
 
-    match comparison_op {
-        ">" => Ok(agg_value > alert.threshold),
-        "<" => Ok(agg_value < alert.threshold),
-        _ => Err(format!("Unknown comparison operator: {}", comparison_op)),
+/// Sample the docker containers and processes the given variables refer to
+async fn take_samples(vars: &[&AlertVar], sys: &mut System) -> HashMap<SampleKey, f64> {
+    let mut values = HashMap::new();
+    if vars.iter().any(|var| var.cat == "docker") {
+        match collect_info::get_docker_containers().await {
+            Some(info) => values.extend(docker_samples(&info.containers)),
+            None => warn!("Can't get docker containers info for alerts"),
+        }
+    }
+    let patterns: HashSet<&str> = vars
+        .iter()
+        .filter(|var| var.cat == "proc")
+        .map(|var| var.resrc.as_str())
+        .collect();
+    if !patterns.is_empty() {
+        // Command lines aren't refreshed by default, but are needed for regexes
+        sys.refresh_processes_specifics(
+            ProcessesToUpdate::All,
+            true,
+            ProcessRefreshKind::nothing()
+                .with_cpu()
+                .with_memory()
+                .with_cmd(UpdateKind::OnlyIfNotSet),
+        );
+        let processes = collect_info::collect_processes_info(sys).processes;
+        values.extend(process_samples(&processes, &patterns));
     }
+    values
 }
 
-/// Update alert state in the database
-fn update_alert_state(db: &Database, alert: &Alert) -> Result<(), String> {
-    // Get current alerts
-    let alerts_json = match db.get_kv_str("alerts") {
-        Ok(Some(json)) => json,
-        Ok(None) => "[]".to_string(),
-        Err(e) => return Err(format!("Database error: {}", e)),
-    };
+fn docker_samples(containers: &[DockerContainer]) -> HashMap<SampleKey, f64> {
+    let mut values = HashMap::new();
+    for container in containers {
+        for &(_, var) in ALERT_VARIABLES.iter().filter(|v| v.0 == "docker") {
+            if let Some(value) = docker_value(container, var) {
+                let name = container_name(container).to_string();
+                values.insert(("docker".to_string(), name, var.to_string()), value);
+            }
+        }
+    }
+    values
+}
 
-    let mut alerts = serde_json::from_str::<Vec<Alert>>(&alerts_json)
-        .map_err(|e| format!("Failed to parse alerts JSON: {}", e))?;
+/// Name a container is referred to by in alerts
+pub fn container_name(container: &DockerContainer) -> &str {
+    container.name.trim_start_matches('/')
+}
+
+/// Numeric value of a docker alert variable, flags are 1 when set
+fn docker_value(container: &DockerContainer, var: &str) -> Option<f64> {
+    let flag = |set: bool| if set { 1.0 } else { 0.0 };
+    match var {
+        "running" => Some(flag(container.state == "running")),
+        "restarts" => Some(container.restart_count as f64),
+        "cpu_usage" => Some(container.cpu_usage),
+        "mem_usage" => (container.mem_limit > 0)
+            .then(|| container.mem_usage as f64 / container.mem_limit as f64 * 100.0),
+        "unhealthy" => Some(flag(container.health == "unhealthy")),
+        _ => None,
+    }
+}
 
-    // Update the alert in the list
-    for a in &mut alerts {
-        if a.id == alert.id {
-            a.firing = alert.firing;
-            break;
+/// Number of matching processes for every pattern, and the highest CPU
+/// and memory usage among them when there are any
+fn process_samples(processes: &[ProcessInfo], patterns: &HashSet<&str>) -> HashMap<SampleKey, f64> {
+    let mut values = HashMap::new();
+    for &pattern in patterns {
+        let matcher = match ProcessMatcher::new(pattern) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        let matching: Vec<&ProcessInfo> = processes.iter().filter(|p| matcher.matches(p)).collect();
+        let key = |var: &str| ("proc".to_string(), pattern.to_string(), var.to_string());
+        values.insert(key("count"), matching.len() as f64);
+        if let Some(cpu) = matching.iter().map(|p| p.cpu as f64).reduce(f64::max) {
+            values.insert(key("cpu_usage"), cpu);
+        }
+        if let Some(mem) = matching.iter().map(|p| p.mem).max() {
+            values.insert(key("mem"), mem as f64);
         }
     }
+    values
+}
 
-    // Save back to database
-    let updated_json =
-        serde_json::to_string(&alerts).map_err(|e| format!("Failed to serialize alerts: {}", e))?;
+/// How a `proc` resource selects processes: `re:` followed by a regex
+/// searched in the command line, or else the exact process name
+enum ProcessMatcher {
+    Name(String),
+    Cmdline(Regex),
+}
+
+impl ProcessMatcher {
+    fn new(resource: &str) -> Result<Self, String> {
+        match resource.strip_prefix("re:") {
+            Some(pattern) => Regex::new(pattern)
+                .map(ProcessMatcher::Cmdline)
+                .map_err(|e| format!("Invalid process regex \"{}\": {}", pattern, e)),
+            None if resource.is_empty() => Err("Process name can't be empty".to_string()),
+            None => Ok(ProcessMatcher::Name(resource.to_string())),
+        }
+    }
 
-    db.set_kv_str("alerts", &updated_json)
-        .map_err(|e| format!("Failed to save alerts: {}", e))
+    fn matches(&self, process: &ProcessInfo) -> bool {
+        match self {
+            ProcessMatcher::Name(name) => process.name == *name,
+            ProcessMatcher::Cmdline(regex) => regex.is_match(&process.cmd),
+        }
+    }
 }
 
-/// Send a notification through the configured method
-async fn send_notification(method: &NotificationMethod, message: &str) -> Result<(), String> {
-    match &method.config {
-        NotificationConfig::WebHook(webhook) => send_webhook_notification(webhook, message).await,
+/// Check that an alert can be evaluated before it's saved
+pub fn validate_alert(alert: &Alert) -> Result<(), String> {
+    if alert.renotify_interval.is_some_and(|interval| interval < 1) {
+        return Err("Re-notify interval must be at least 1 minute".to_string());
+    }
+    let mut previous = 0;
+    for escalation in &alert.escalations {
+        if escalation.after < 1 || escalation.after < previous {
+            return Err("Escalations must be ordered, at least 1 minute after firing".to_string());
+        }
+        if escalation.notif_methods.is_empty() {
+            return Err("Escalations need at least one notification method".to_string());
+        }
+        previous = escalation.after;
+    }
+    if let Some(condition) = &alert.condition {
+        return validate_condition(alert, condition);
+    }
+    match alert.kind.as_str() {
+        "threshold" => {}
+        "rate" | "forecast" => {
+            if !matches!(alert.operator.as_str(), ">" | ">=" | "<" | "<=") {
+                return Err(format!(
+                    "The {} operator can't be used with {} alerts",
+                    alert.operator, alert.kind
+                ));
+            }
+        }
+        _ => return Err(format!("Unknown alert kind \"{}\"", alert.kind)),
+    }
+    if SAMPLED_CATEGORIES.contains(&alert.var.cat.as_str()) && alert.kind != "threshold" {
+        return Err(format!(
+            "Only threshold alerts are supported for {}",
+            alert.var.cat
+        ));
+    }
+    if alert.var.cat == "proc" {
+        ProcessMatcher::new(&alert.var.resrc)?;
+    }
+    if alert.kind == "forecast"
+        && !alert
+            .horizon
+            .is_some_and(|horizon| horizon.is_finite() && horizon > 0.0)
+    {
+        return Err("Forecast alerts need a positive horizon in hours".to_string());
+    }
+    if !ALERT_OPERATORS.contains(&alert.operator.as_str()) {
+        return Err(format!(
+            "Unknown operator \"{}\", expected one of {}",
+            alert.operator,
+            ALERT_OPERATORS.join(" ")
+        ));
+    }
+    if !ALERT_VARIABLES.contains(&(alert.var.cat.as_str(), alert.var.var.as_str())) {
+        return Err(format!(
+            "Unknown alert variable {}/{}",
+            alert.var.cat, alert.var.var
+        ));
     }
+    if !alert.threshold.is_finite() {
+        return Err("Threshold must be a finite number".to_string());
+    }
+    if alert.time_window.is_nan() || alert.time_window * 60.0 < 1.0 {
+        return Err("Time window must be at least 1 second".to_string());
+    }
+    window_table(alert)?;
+    if let Some(recovery) = alert.recovery_threshold {
+        let valid = match alert.operator.as_str() {
+            ">" | ">=" => recovery <= alert.threshold,
+            "<" | "<=" => recovery >= alert.threshold,
+            _ => {
+                return Err(format!(
+                    "Recovery threshold can't be used with the {} operator",
+                    alert.operator
+                ));
+            }
+        };
+        if !recovery.is_finite() || !valid {
+            return Err(format!(
+                "Recovery threshold {} must be on the normal side of the threshold {}",
+                recovery, alert.threshold
+            ));
+        }
+    }
+    Ok(())
 }
 
-/// Build an HTTP client, loading CA certificates manually if the default
-/// platform verifier fails (e.g. in scratch Docker containers).
-fn build_http_client() -> Result<Client, String> {
-    // Try the default builder first (uses rustls-platform-verifier)
-    match Client::builder().build() {
-        Ok(client) => return Ok(client),
-        Err(e) => {
-            debug!(
-                "Default HTTP client builder failed ({}), trying manual CA cert loading",
-                e
-            );
+/// Check every metric of a composite condition like a standalone alert
+fn validate_condition(alert: &Alert, condition: &AlertCondition) -> Result<(), String> {
+    match condition {
+        AlertCondition::And { conditions } | AlertCondition::Or { conditions } => {
+            if conditions.is_empty() {
+                return Err("AND and OR conditions need at least one sub-condition".to_string());
+            }
+            conditions
+                .iter()
+                .try_for_each(|condition| validate_condition(alert, condition))
         }
+        AlertCondition::Not { condition } => validate_condition(alert, condition),
+        AlertCondition::Metric(check) => validate_alert(&metric_alert(alert, check)),
+    }
+}
+
+/// Standalone alert for a metric of a composite condition.
+/// Recovery thresholds don't apply to individual metrics.
+fn metric_alert(alert: &Alert, check: &AlertCheck) -> Alert {
+    Alert {
+        id: alert.id.clone(),
+        name: String::new(),
+        var: check.var.clone(),
+        kind: check.kind.clone(),
+        threshold: check.threshold,
+        recovery_threshold: None,
+        horizon: check.horizon,
+        operator: check.operator.clone(),
+        time_window: check.time_window,
+        resolution: check.resolution.clone(),
+        condition: None,
+        enabled: alert.enabled,
+        firing: false,
+        notif_methods: Vec::new(),
+        renotify_interval: None,
+        escalations: Vec::new(),
+        fired_at: None,
+        last_notified: None,
+        escalation_level: 0,
+        template: None,
     }
+}
 
-    // Fallback: load CA certs manually and use tls_certs_only() to bypass
-    // rustls-platform-verifier, which can fail in minimal containers (e.g. FROM scratch)
-    let cert_paths = [
-        "/etc/ssl/certs/ca-certificates.crt",
-        "/etc/pki/tls/certs/ca-bundle.crt",
-        "/etc/ssl/ca-bundle.pem",
-    ];
-
-    for path in &cert_paths {
-        if let Ok(pem_data) = std::fs::read(path) {
-            let certs = Certificate::from_pem_bundle(&pem_data)
-                .map_err(|e| format!("Failed to parse CA certificates from {}: {}", path, e))?;
-            if !certs.is_empty() {
-                debug!("Loaded {} CA certificates from {}", certs.len(), path);
-                return Client::builder()
-                    .tls_certs_only(certs)
-                    .build()
-                    .map_err(|e| format!("Failed to build HTTP client with manual certs: {}", e));
+/// Evaluate a composite condition, each metric over its own window
+fn evaluate_condition(
+    db: &Database,
+    samples: &SampleHistory,
+    alert: &Alert,
+    condition: &AlertCondition,
+) -> Result<bool, String> {
+    Ok(match condition {
+        AlertCondition::And { conditions } => {
+            for condition in conditions {
+                if !evaluate_condition(db, samples, alert, condition)? {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        AlertCondition::Or { conditions } => {
+            for condition in conditions {
+                if evaluate_condition(db, samples, alert, condition)? {
+                    return Ok(true);
+                }
             }
+            false
+        }
+        AlertCondition::Not { condition } => !evaluate_condition(db, samples, alert, condition)?,
+        AlertCondition::Metric(check) => {
+            check_alert_condition(db, samples, &metric_alert(alert, check))?.firing
         }
+    })
+}
+
+/// Decide whether an alert fires given the extremes of its window.
+/// The condition has to hold for every value in the window. While firing,
+/// it is checked against the recovery threshold instead, so the alert only
+/// resolves once the metric is clearly back to normal.
+fn evaluate(alert: &Alert, stats: WindowStats, currently_firing: bool) -> Result<bool, String> {
+    if stats.count == 0 {
+        return Ok(false); // firing=false if there's no data
     }
+    let threshold = match alert.recovery_threshold {
+        Some(recovery) if currently_firing => recovery,
+        _ => alert.threshold,
+    };
+    Ok(match alert.operator.as_str() {
+        ">" => stats.min > threshold,
+        ">=" => stats.min >= threshold,
+        "<" => stats.max < threshold,
+        "<=" => stats.max <= threshold,
+        "==" => stats.min == threshold && stats.max == threshold,
+        // Without the individual values, only a window entirely on one side is known to differ
+        "!=" => threshold < stats.min || threshold > stats.max,
+        _ => return Err(format!("Unknown operator: {}", alert.operator)),
+    })
+}
 
-    Err(
-        "Failed to build HTTP client: platform verifier unavailable and no CA certificates found"
-            .to_string(),
-    )
+/// The extreme of the window that decided the outcome of an alert
+fn decisive_value(operator: &str, threshold: f64, stats: WindowStats) -> f64 {
+    match operator {
+        ">" | ">=" => stats.min,
+        "<" | "<=" => stats.max,
+        _ => {
+            // Farthest from the threshold for ==, closest for !=
+            let (near, far) = if (stats.min - threshold).abs() <= (stats.max - threshold).abs() {
+                (stats.min, stats.max)
+            } else {
+                (stats.max, stats.min)
+            };
+            if operator == "==" { far } else { near }
+        }
+    }
 }
 
-/// Send a webhook notification
-async fn send_webhook_notification(webhook: &WebHookNotif, message: &str) -> Result<(), String> {
-    let client = build_http_client()?;
+/// Least squares fit of `y = slope * x + intercept`, None without at least two distinct x
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    if points.len() < 2 {
+        return None;
+    }
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut sxx, mut sxy) = (0.0, 0.0);
+    for (x, y) in points {
+        sxx += (x - mean_x) * (x - mean_x);
+        sxy += (x - mean_x) * (y - mean_y);
+    }
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, mean_y - slope * mean_x))
+}
+
+/// Check if an alert condition is met consistently across the entire time window
+fn check_alert_condition(
+    db: &Database,
+    samples: &SampleHistory,
+    alert: &Alert,
+) -> Result<Evaluation, String> {
+    if let Some(condition) = &alert.condition {
+        return Ok(Evaluation {
+            firing: evaluate_condition(db, samples, alert, condition)?,
+            value: None,
+        });
+    }
+
+    let time_window_secs = (alert.time_window * 60.0).round() as i64;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| format!("Time error: {}", e))?
+        .as_secs() as i64;
 
-    // Replace placeholder with actual message
-    let url = webhook.url.replace("{notif_msg}", message);
+    let start_time = now - time_window_secs;
 
-    // Prepare headers
-    let mut headers = reqwest::header::HeaderMap::new();
-    for (key, value) in &webhook.headers {
-        headers.insert(
-            reqwest::header::HeaderName::from_bytes(key.as_bytes())
-                .map_err(|e| format!("Invalid header name: {}", e))?,
-            reqwest::header::HeaderValue::from_str(value)
-                .map_err(|e| format!("Invalid header value: {}", e))?,
+    if SAMPLED_CATEGORIES.contains(&alert.var.cat.as_str()) {
+        let (before, values) = samples.values(&alert.var, start_time);
+        let stats = if alert.var.var == "restarts" {
+            // Restarts are counted since the last sample before the window
+            match (before.or(values.first().copied()), values.last()) {
+                (Some(first), Some(last)) => vec![last - first],
+                _ => Vec::new(),
+            }
+        } else {
+            values
+        }
+        .into_iter()
+        .fold(
+            WindowStats {
+                min: f64::NAN,
+                max: f64::NAN,
+                count: 0,
+            },
+            |stats, value| WindowStats {
+                min: value.min(stats.min),
+                max: value.max(stats.max),
+                count: stats.count + 1,
+            },
         );
+        return Ok(Evaluation {
+            firing: evaluate(alert, stats, alert.firing)?,
+            value: (stats.count > 0)
+                .then(|| decisive_value(&alert.operator, alert.threshold, stats)),
+        });
     }
 
-    // Build request based on method
-    let mut request_builder = match webhook.method.to_uppercase().as_str() {
-        "GET" => client.get(&url),
-        "POST" => {
-            let body = webhook.body.replace("{notif_msg}", message);
-            client.post(&url).body(body)
-        }
-        "PUT" => {
-            let body = webhook.body.replace("{notif_msg}", message);
-            client.put(&url).body(body)
-        }
-        "PATCH" => {
-            let body = webhook.body.replace("{notif_msg}", message);
-            client.patch(&url).body(body)
+    let table_suffix = window_table(alert)?;
+
+    let mut query_params: Vec<rusqlite::types::Value> = vec![start_time.into()];
+    let (table, filter) = match alert.var.cat.as_str() {
+        // System metrics are in general_* tables
+        "sys" => (format!("general_{}", table_suffix), "timestamp >= ?"),
+        "net" | "disk" => {
+            // Network or disk metrics need to filter by resource name
+            query_params.push(alert.var.resrc.clone().into());
+            (
+                format!("{}_{}", alert.var.cat, table_suffix),
+                "timestamp >= ? AND name = ?",
+            )
         }
-        "DELETE" => client.delete(&url),
-        _ => return Err(format!("Unsupported HTTP method: {}", webhook.method)),
+        _ => return Err(format!("Unknown category: {}", alert.var.cat)),
     };
+    let var = &alert.var.var;
+    let conn = db.conn.lock().unwrap();
 
-    // Add headers
-    request_builder = request_builder.headers(headers);
+    if alert.kind == "threshold" {
+        // Rolled-up rows keep their extremes, fall back to the average for rows that don't
+        let (min, max) = if table_suffix == "s" {
+            (var.clone(), var.clone())
+        } else {
+            (
+                format!("COALESCE({var}_min, {var})"),
+                format!("COALESCE({var}_max, {var})"),
+            )
+        };
+        let query =
+            format!("SELECT MIN({min}), MAX({max}), COUNT({var}) FROM {table} WHERE {filter}");
+        let stats = conn
+            .query_row(
+                &query,
+                rusqlite::params_from_iter(query_params.iter()),
+                |row| {
+                    Ok(WindowStats {
+                        min: row.get::<_, Option<f64>>(0)?.unwrap_or(f64::NAN),
+                        max: row.get::<_, Option<f64>>(1)?.unwrap_or(f64::NAN),
+                        count: row.get(2)?,
+                    })
+                },
+            )
+            .map_err(|e| format!("Database query error: {}", e))?;
 
-    // Send request
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send webhook request: {:?}", e))?;
+        return Ok(Evaluation {
+            firing: evaluate(alert, stats, alert.firing)?,
+            value: (stats.count > 0)
+                .then(|| decisive_value(&alert.operator, alert.threshold, stats)),
+        });
+    }
 
-    if response.status().is_success() {
-        info!("Webhook notification sent successfully");
-        Ok(())
-    } else {
-        Err(format!(
-            "Webhook request failed with status: {}",
-            response.status()
-        ))
+    // Trends are fitted on the averages, with time in hours since the window started
+    let query = format!(
+        "SELECT timestamp, {var} FROM {table} WHERE {filter} AND {var} IS NOT NULL ORDER BY timestamp"
+    );
+    let points = conn
+        .prepare(&query)
+        .and_then(|mut stmt| {
+            stmt.query_map(rusqlite::params_from_iter(query_params.iter()), |row| {
+                Ok((
+                    (row.get::<_, i64>(0)? - start_time) as f64 / 3600.0,
+                    row.get(1)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<(f64, f64)>>>()
+        })
+        .map_err(|e| format!("Database query error: {}", e))?;
+    let Some((slope, intercept)) = linear_fit(&points) else {
+        // Not enough data for a trend
+        return Ok(Evaluation {
+            firing: false,
+            value: None,
+        });
+    };
+
+    let single = |value: f64| WindowStats {
+        min: value,
+        max: value,
+        count: 1,
+    };
+    match alert.kind.as_str() {
+        "rate" => Ok(Evaluation {
+            firing: evaluate(alert, single(slope), alert.firing)?,
+            value: Some(slope),
+        }),
+        "forecast" => {
+            // A straight line crosses the threshold within the horizon
+            // if the condition holds at either end of it
+            let elapsed = time_window_secs as f64 / 3600.0;
+            let current = intercept + slope * elapsed;
+            let predicted = intercept + slope * (elapsed + alert.horizon.unwrap_or_default());
+            Ok(Evaluation {
+                firing: evaluate(alert, single(current), alert.firing)?
+                    || evaluate(alert, single(predicted), alert.firing)?,
+                value: Some(predicted),
+            })
+        }
+        _ => Err(format!("Unknown alert kind: {}", alert.kind)),
     }
 }
 
+/// Suffix of the data tables an alert is evaluated on.
+/// Without an explicit resolution, the coarsest one still having a few rows in the window.
+fn window_table(alert: &Alert) -> Result<&'static str, String> {
+    match alert.resolution.as_deref() {
+        Some("second") => Ok("s"),
+        Some("minute") => Ok("m"),
+        Some("hour") => Ok("h"),
+        Some(resolution) => Err(format!(
+            "Unknown resolution \"{}\", expected second, minute or hour",
+            resolution
+        )),
+        None if alert.time_window < 5.0 => Ok("s"),
+        None if alert.time_window <= 120.0 => Ok("m"),
+        None => Ok("h"),
+    }
+}
+
+/// Update alert state in the database
+fn update_alert_state(db: &Database, alert: &Alert) -> Result<(), String> {
+    db.set_alert_state(alert)
+        .map_err(|e| format!("Failed to save alert state: {}", e))
+}
+
 /// Get a user-friendly name for a variable
 fn get_var_friendly_name(var: &AlertVar) -> String {
     match (var.cat.as_str(), var.var.as_str()) {
@@ -343,12 +1004,151 @@ fn get_var_friendly_name(var: &AlertVar) -> String {
         ("disk", "read_rate") => "Disk Read Rate".to_string(),
         ("disk", "write_rate") => "Disk Write Rate".to_string(),
         ("disk", "disk_usage") => "Disk Usage".to_string(),
+        ("docker", "running") => "Container Running".to_string(),
+        ("docker", "restarts") => "Container Restarts".to_string(),
+        ("docker", "cpu_usage") => "Container CPU Usage".to_string(),
+        ("docker", "mem_usage") => "Container Memory Usage".to_string(),
+        ("docker", "unhealthy") => "Container Unhealthy".to_string(),
+        ("proc", "count") => "Process Count".to_string(),
+        ("proc", "cpu_usage") => "Process CPU Usage".to_string(),
+        ("proc", "mem") => "Process Memory".to_string(),
         _ => format!("{} {}", var.cat, var.var),
     }
 }
 
 /// Format an alert message with appropriate units and verbs
 fn format_alert_message(alert: &Alert, is_firing: bool) -> String {
+    if let Some(condition) = &alert.condition {
+        let description = describe_condition(alert, condition, false);
+        return if is_firing {
+            format!("ALERT: {}", description)
+        } else {
+            format!("RESOLVED: condition no longer met: {}", description)
+        };
+    }
+
+    let (subject, condition, details) = describe_metric(alert);
+    if is_firing {
+        format!("ALERT: {} {} ({})", subject, condition, details)
+    } else {
+        format!(
+            "RESOLVED: {} no longer {} (back to normal)",
+            subject, condition
+        )
+    }
+}
+
+/// Variables of a notification for message templates
+fn alert_context(alert: &Alert, notification: &Notification) -> AlertContext {
+    let join = |items: Vec<String>| {
+        let mut unique: Vec<String> = Vec::new();
+        for item in items {
+            if !unique.contains(&item) {
+                unique.push(item);
+            }
+        }
+        unique.join(", ")
+    };
+    let (default_name, metric, resource, threshold) = match &alert.condition {
+        Some(condition) => {
+            let vars = alert_vars(alert);
+            (
+                describe_condition(alert, condition, false),
+                join(
+                    vars.iter()
+                        .map(|(var, _)| get_var_friendly_name(var))
+                        .collect(),
+                ),
+                join(
+                    vars.iter()
+                        .filter(|(var, _)| var.cat != "sys")
+                        .map(|(var, _)| var.resrc.clone())
+                        .collect(),
+                ),
+                String::new(),
+            )
+        }
+        None => (
+            describe_metric(alert).0,
+            get_var_friendly_name(&alert.var),
+            if alert.var.cat != "sys" {
+                alert.var.resrc.clone()
+            } else {
+                String::new()
+            },
+            format_metric_value(&alert.var, alert.threshold),
+        ),
+    };
+    // Rates are per hour
+    let unit = if alert.condition.is_none() && alert.kind == "rate" {
+        "/h"
+    } else {
+        ""
+    };
+    let now = chrono::Utc::now().timestamp();
+    AlertContext {
+        alert_id: alert.id.clone(),
+        alert_name: if alert.name.is_empty() {
+            default_name
+        } else {
+            alert.name.clone()
+        },
+        metric,
+        resource,
+        value: notification
+            .value
+            .map(|value| format!("{}{}", format_metric_value(&alert.var, value), unit))
+            .unwrap_or_default(),
+        threshold: if threshold.is_empty() {
+            threshold
+        } else {
+            format!("{}{}", threshold, unit)
+        },
+        host: System::host_name().unwrap_or_default(),
+        state: if alert.firing { "firing" } else { "resolved" }.to_string(),
+        transition: notification.transition.to_string(),
+        started_at: notification
+            .fired_at
+            .and_then(|t| chrono::Local.timestamp_opt(t, 0).single())
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default(),
+        duration: notification
+            .fired_at
+            .map(|t| format_duration(now - t))
+            .unwrap_or_default(),
+        message: notification.message.clone(),
+    }
+}
+
+/// Readable form of a composite condition, nested expressions in parentheses
+fn describe_condition(alert: &Alert, condition: &AlertCondition, nested: bool) -> String {
+    let join = |conditions: &[AlertCondition], separator: &str| {
+        let joined = conditions
+            .iter()
+            .map(|condition| describe_condition(alert, condition, true))
+            .collect::<Vec<_>>()
+            .join(separator);
+        if nested && conditions.len() > 1 {
+            format!("({})", joined)
+        } else {
+            joined
+        }
+    };
+    match condition {
+        AlertCondition::And { conditions } => join(conditions, " AND "),
+        AlertCondition::Or { conditions } => join(conditions, " OR "),
+        AlertCondition::Not { condition } => {
+            format!("NOT {}", describe_condition(alert, condition, true))
+        }
+        AlertCondition::Metric(check) => {
+            let (subject, condition, details) = describe_metric(&metric_alert(alert, check));
+            format!("{} {} ({})", subject, condition, details)
+        }
+    }
+}
+
+/// Metric, condition and window details of a single metric alert
+fn describe_metric(alert: &Alert) -> (String, String, String) {
     // Get the resource identifier if applicable
     let resource = if alert.var.cat != "sys" {
         format!(" ({})", alert.var.resrc)
@@ -356,66 +1156,95 @@ fn format_alert_message(alert: &Alert, is_firing: bool) -> String {
         "".to_string()
     };
 
-    // Get unit for the metric
-    let (value_with_unit, verb) = match (
-        alert.var.cat.as_str(),
-        alert.var.var.as_str(),
-        alert.operator.as_str(),
-    ) {
-        // System metrics
-        (_, "cpu_usage", ">") => (format!("{}%", alert.threshold), "exceeded"),
-        (_, "cpu_usage", "<") => (format!("{}%", alert.threshold), "dropped below"),
-        (_, "mem_usage", ">") => (format!("{}%", alert.threshold), "exceeded"),
-        (_, "mem_usage", "<") => (format!("{}%", alert.threshold), "dropped below"),
-        (_, "swap_usage", ">") => (format!("{}%", alert.threshold), "exceeded"),
-        (_, "swap_usage", "<") => (format!("{}%", alert.threshold), "dropped below"),
-        (_, "disk_usage", ">") => (format!("{}%", alert.threshold), "exceeded"),
-        (_, "disk_usage", "<") => (format!("{}%", alert.threshold), "dropped below"),
-        (_, "load_avg_1", ">") => (format!("{}", alert.threshold), "exceeded"),
-        (_, "load_avg_1", "<") => (format!("{}", alert.threshold), "dropped below"),
-        (_, "load_avg_5", ">") => (format!("{}", alert.threshold), "exceeded"),
-        (_, "load_avg_5", "<") => (format!("{}", alert.threshold), "dropped below"),
-        (_, "load_avg_15", ">") => (format!("{}", alert.threshold), "exceeded"),
-        (_, "load_avg_15", "<") => (format!("{}", alert.threshold), "dropped below"),
-
-        // Network metrics
-        ("net", "rx_rate", ">") => (format_bytes_per_sec(alert.threshold), "exceeded"),
-        ("net", "rx_rate", "<") => (format_bytes_per_sec(alert.threshold), "dropped below"),
-        ("net", "tx_rate", ">") => (format_bytes_per_sec(alert.threshold), "exceeded"),
-        ("net", "tx_rate", "<") => (format_bytes_per_sec(alert.threshold), "dropped below"),
-
-        // Disk metrics
-        ("disk", "read_rate", ">") => (format_bytes_per_sec(alert.threshold), "exceeded"),
-        ("disk", "read_rate", "<") => (format_bytes_per_sec(alert.threshold), "dropped below"),
-        ("disk", "write_rate", ">") => (format_bytes_per_sec(alert.threshold), "exceeded"),
-        ("disk", "write_rate", "<") => (format_bytes_per_sec(alert.threshold), "dropped below"),
-
-        // Default case
-        (_, _, ">") => (format!("{}", alert.threshold), "exceeded"),
-        (_, _, "<") => (format!("{}", alert.threshold), "is below"),
-        _ => (format!("{}", alert.threshold), "equals"),
+    let value_with_unit = format_metric_value(&alert.var, alert.threshold);
+    let verb = match alert.operator.as_str() {
+        ">" => "exceeded",
+        ">=" => "reached",
+        "<" => "dropped below",
+        "<=" => "dropped to",
+        "!=" => "differs from",
+        _ => "equals",
+    };
+    let (condition, details) = match alert.kind.as_str() {
+        "rate" => {
+            let verb = match alert.operator.as_str() {
+                ">" | ">=" => "changing faster than",
+                _ => "changing slower than",
+            };
+            (
+                format!("{} {}/h", verb, value_with_unit),
+                format!("over {}", format_window(alert.time_window)),
+            )
+        }
+        "forecast" => {
+            let verb = match alert.operator.as_str() {
+                ">" => "exceed",
+                ">=" => "reach",
+                "<" => "drop below",
+                _ => "drop to",
+            };
+            (
+                format!(
+                    "predicted to {} {} within {} h",
+                    verb,
+                    value_with_unit,
+                    alert.horizon.unwrap_or_default()
+                ),
+                format!("based on the last {}", format_window(alert.time_window)),
+            )
+        }
+        _ => (
+            format!("{} {}", verb, value_with_unit),
+            format!("sustained for {}", format_window(alert.time_window)),
+        ),
     };
 
-    if is_firing {
-        format!(
-            "ALERT: {}{} {} {} (sustained for {} min)",
-            get_var_friendly_name(&alert.var),
-            resource,
-            verb,
-            value_with_unit,
-            alert.time_window
-        )
+    (
+        format!("{}{}", get_var_friendly_name(&alert.var), resource),
+        condition,
+        details,
+    )
+}
+
+/// Format a value of a metric with its unit, rounded to two decimals
+fn format_metric_value(var: &AlertVar, value: f64) -> String {
+    let value = (value * 100.0).round() / 100.0;
+    match var.var.as_str() {
+        "cpu_usage" | "mem_usage" | "swap_usage" | "disk_usage" => format!("{}%", value),
+        "rx_rate" | "tx_rate" | "read_rate" | "write_rate" => format_bytes_per_sec(value),
+        "mem" => format_bytes(value),
+        _ => format!("{}", value),
+    }
+}
+
+/// Format a duration given in seconds, down to the minute
+fn format_duration(secs: i64) -> String {
+    let minutes = secs.max(0) / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{} min", minutes),
+        (0, _) => format!("{} h {} min", hours, minutes),
+        _ => format!("{} d {} h", days, hours),
+    }
+}
+
+/// Format a time window given in minutes, in seconds when it isn't whole minutes
+fn format_window(minutes: f64) -> String {
+    let secs = (minutes * 60.0).round() as i64;
+    if secs % 60 == 0 {
+        format!("{} min", secs / 60)
     } else {
-        format!(
-            "RESOLVED: {}{} no longer {} {} (back to normal)",
-            get_var_friendly_name(&alert.var),
-            resource,
-            verb,
-            value_with_unit
-        )
+        format!("{} s", secs)
     }
 }
 
+/// Format bytes with appropriate units
+fn format_bytes(bytes: f64) -> String {
+    format_bytes_per_sec(bytes)
+        .trim_end_matches("/s")
+        .to_string()
+}
+
 /// Format bytes per second with appropriate units
 fn format_bytes_per_sec(bytes_per_sec: f64) -> String {
     const KIB: f64 = 1024.0;
@@ -431,3 +1260,468 @@ fn format_bytes_per_sec(bytes_per_sec: f64) -> String {
     let value = bytes_per_sec / KIB.powi(exp);
     format!("{:.2} {}", value, units[exp as usize])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AlertEscalation, AlertHistoryQuery};
+    use crate::notifications::tests::method;
+
+    fn alert(operator: &str, threshold: f64, recovery_threshold: Option<f64>) -> Alert {
+        Alert {
+            id: "a".to_string(),
+            var: AlertVar {
+                cat: "sys".to_string(),
+                var: "cpu_usage".to_string(),
+                resrc: "sys".to_string(),
+            },
+            kind: "threshold".to_string(),
+            threshold,
+            recovery_threshold,
+            horizon: None,
+            operator: operator.to_string(),
+            name: String::new(),
+            time_window: 5.0,
+            resolution: None,
+            condition: None,
+            enabled: true,
+            firing: false,
+            notif_methods: Vec::new(),
+            renotify_interval: None,
+            escalations: Vec::new(),
+            fired_at: None,
+            last_notified: None,
+            escalation_level: 0,
+            template: None,
+        }
+    }
+
+    fn stats(min: f64, max: f64) -> WindowStats {
+        WindowStats { min, max, count: 5 }
+    }
+
+    #[test]
+    fn test_evaluate_operators() {
+        let cases = [
+            (">", 90.0, stats(91.0, 99.0), true),
+            (">", 90.0, stats(90.0, 99.0), false),
+            (">=", 90.0, stats(90.0, 99.0), true),
+            ("<", 10.0, stats(1.0, 9.0), true),
+            ("<=", 10.0, stats(1.0, 10.0), true),
+            ("<=", 10.0, stats(1.0, 11.0), false),
+            ("==", 1.0, stats(1.0, 1.0), true),
+            ("==", 1.0, stats(0.0, 1.0), false),
+            ("!=", 1.0, stats(0.0, 0.0), true),
+            ("!=", 1.0, stats(0.0, 2.0), false),
+        ];
+        for (operator, threshold, stats, expected) in cases {
+            let alert = alert(operator, threshold, None);
+            assert_eq!(
+                evaluate(&alert, stats, false).unwrap(),
+                expected,
+                "{} {} over {:?}",
+                operator,
+                threshold,
+                stats
+            );
+        }
+
+        let empty = WindowStats {
+            min: f64::NAN,
+            max: f64::NAN,
+            count: 0,
+        };
+        assert!(!evaluate(&alert("!=", 1.0, None), empty, true).unwrap());
+    }
+
+    #[test]
+    fn test_evaluate_hysteresis() {
+        let alert = alert(">", 90.0, Some(75.0));
+        // Fires only above the threshold
+        assert!(!evaluate(&alert, stats(80.0, 95.0), false).unwrap());
+        assert!(evaluate(&alert, stats(91.0, 95.0), false).unwrap());
+        // Keeps firing until the window drops below the recovery threshold
+        assert!(evaluate(&alert, stats(80.0, 95.0), true).unwrap());
+        assert!(!evaluate(&alert, stats(70.0, 95.0), true).unwrap());
+    }
+
+    #[test]
+    fn test_validate_alert() {
+        assert!(validate_alert(&alert(">=", 90.0, Some(75.0))).is_ok());
+        assert!(validate_alert(&alert("=>", 90.0, None)).is_err());
+        assert!(validate_alert(&alert(">", 90.0, Some(95.0))).is_err());
+        assert!(validate_alert(&alert("<", 10.0, Some(5.0))).is_err());
+        assert!(validate_alert(&alert("==", 1.0, Some(1.0))).is_err());
+
+        let mut unknown_var = alert(">", 1.0, None);
+        unknown_var.var.var = "cpu_usage); DROP TABLE alerts; --".to_string();
+        assert!(validate_alert(&unknown_var).is_err());
+    }
+
+    #[test]
+    fn test_window_resolution() {
+        let mut a = alert(">", 95.0, None);
+        for (window, table) in [
+            (0.5, "s"),
+            (4.5, "s"),
+            (5.0, "m"),
+            (120.0, "m"),
+            (180.0, "h"),
+        ] {
+            a.time_window = window;
+            assert_eq!(window_table(&a).unwrap(), table, "{} min", window);
+        }
+        a.resolution = Some("minute".to_string());
+        assert_eq!(window_table(&a).unwrap(), "m");
+        a.resolution = Some("ms".to_string());
+        assert!(validate_alert(&a).is_err());
+
+        a.resolution = None;
+        a.time_window = 0.5;
+        assert!(validate_alert(&a).is_ok());
+        a.time_window = 0.01;
+        assert!(validate_alert(&a).is_err());
+        a.time_window = f64::NAN;
+        assert!(validate_alert(&a).is_err());
+    }
+
+    #[test]
+    fn test_second_data_window() {
+        let db = Database::new(":memory:").unwrap();
+        let now = chrono::Utc::now().timestamp();
+        {
+            let conn = db.conn.lock().unwrap();
+            for (age, cpu) in [(100, 10.0), (20, 97.0), (10, 98.0)] {
+                conn.execute(
+                    "INSERT INTO general_s (timestamp, cpu_usage) VALUES (?1, ?2)",
+                    rusqlite::params![now - age, cpu],
+                )
+                .unwrap();
+            }
+        }
+        let history = SampleHistory::default();
+        let mut a = alert(">", 95.0, None);
+        a.time_window = 0.5;
+        let evaluation = check_alert_condition(&db, &history, &a).unwrap();
+        assert!(evaluation.firing);
+        assert_eq!(evaluation.value, Some(97.0));
+        assert!(format_alert_message(&a, true).contains("sustained for 30 s"));
+
+        // The older sample is within a two minute window
+        a.time_window = 2.0;
+        assert!(!check_alert_condition(&db, &history, &a).unwrap().firing);
+    }
+
+    #[test]
+    fn test_alert_context() {
+        let mut a = alert(">", 90.0, None);
+        let notification = Notification {
+            transition: "resolved",
+            message: format_alert_message(&a, false),
+            value: Some(97.234),
+            fired_at: Some(chrono::Utc::now().timestamp() - 3900),
+            silence: None,
+        };
+        let context = alert_context(&a, &notification);
+        assert_eq!(context.alert_name, "CPU Usage");
+        assert_eq!(context.metric, "CPU Usage");
+        assert_eq!(context.resource, "");
+        assert_eq!(context.value, "97.23%");
+        assert_eq!(context.threshold, "90%");
+        assert_eq!(context.state, "resolved");
+        assert_eq!(context.duration, "1 h 5 min");
+        assert!(!context.started_at.is_empty());
+
+        a.name = "Build server busy".to_string();
+        a.kind = "rate".to_string();
+        let context = alert_context(&a, &notification);
+        assert_eq!(context.alert_name, "Build server busy");
+        assert_eq!(context.threshold, "90%/h");
+    }
+
+    #[test]
+    fn test_linear_fit() {
+        let (slope, intercept) = linear_fit(&[(0.0, 1.0), (1.0, 3.0), (2.0, 5.0)]).unwrap();
+        assert!((slope - 2.0).abs() < 1e-9);
+        assert!((intercept - 1.0).abs() < 1e-9);
+
+        // Noisy data still gives the overall trend
+        let (slope, _) = linear_fit(&[(0.0, 0.0), (1.0, 2.0), (2.0, 1.0), (3.0, 3.0)]).unwrap();
+        assert!((slope - 0.8).abs() < 1e-9);
+
+        assert!(linear_fit(&[(1.0, 1.0)]).is_none());
+        assert!(linear_fit(&[(1.0, 1.0), (1.0, 2.0)]).is_none());
+    }
+
+    #[test]
+    fn test_validate_trend_alerts() {
+        let mut rate = alert(">", 5.0, None);
+        rate.kind = "rate".to_string();
+        assert!(validate_alert(&rate).is_ok());
+        rate.operator = "==".to_string();
+        assert!(validate_alert(&rate).is_err());
+
+        let mut forecast = alert(">", 95.0, None);
+        forecast.kind = "forecast".to_string();
+        assert!(validate_alert(&forecast).is_err());
+        forecast.horizon = Some(48.0);
+        assert!(validate_alert(&forecast).is_ok());
+    }
+
+    #[test]
+    fn test_composite_condition() {
+        let condition: AlertCondition = serde_json::from_str(
+            r#"{"op": "and", "conditions": [
+                {"op": "metric", "var": {"cat": "sys", "var": "load_avg_5", "resrc": ""},
+                 "operator": ">", "threshold": 4, "time_window": 5},
+                {"op": "not", "condition":
+                    {"op": "metric", "var": {"cat": "sys", "var": "swap_usage", "resrc": ""},
+                     "operator": "<", "threshold": 50, "time_window": 10}}
+            ]}"#,
+        )
+        .unwrap();
+        let mut composite = alert("", 0.0, None);
+        composite.var = AlertVar::default();
+        composite.condition = Some(condition);
+        assert!(validate_alert(&composite).is_ok());
+        assert_eq!(
+            format_alert_message(&composite, true),
+            "ALERT: 5 Min Load Average exceeded 4 (sustained for 5 min) \
+             AND NOT Swap Usage dropped below 50% (sustained for 10 min)"
+        );
+
+        composite.condition = Some(AlertCondition::Or {
+            conditions: Vec::new(),
+        });
+        assert!(validate_alert(&composite).is_err());
+    }
+
+    fn container(state: &str, restart_count: i64) -> DockerContainer {
+        DockerContainer {
+            id: "c1".to_string(),
+            name: "/web".to_string(),
+            image: "nginx".to_string(),
+            status: String::new(),
+            state: state.to_string(),
+            created: 0,
+            restart_count,
+            health: String::new(),
+            ports: Vec::new(),
+            cpu_usage: 0.0,
+            mem_usage: 0,
+            mem_limit: 0,
+            net_io: [0, 0],
+            disk_io: [0, 0],
+        }
+    }
+
+    #[test]
+    fn test_sample_history() {
+        let mut history = SampleHistory::default();
+        for (t, state, restarts) in [(0, "running", 2), (60, "running", 2), (120, "exited", 3)] {
+            history.record(t, docker_samples(&[container(state, restarts)]), 120);
+        }
+        history.record(180, docker_samples(&[container("running", 4)]), 120);
+        // Only the last sample before the window is kept
+        assert_eq!(history.samples.len(), 3);
+
+        let var = |resrc: &str, var: &str| AlertVar {
+            cat: "docker".to_string(),
+            var: var.to_string(),
+            resrc: resrc.to_string(),
+        };
+        assert_eq!(
+            history.values(&var("web", "running"), 100),
+            (Some(1.0), vec![0.0, 1.0])
+        );
+        assert_eq!(
+            history.values(&var("web", "restarts"), 150),
+            (Some(3.0), vec![4.0])
+        );
+        // No memory limit, no value
+        assert_eq!(
+            history.values(&var("web", "mem_usage"), 0),
+            (None, Vec::new())
+        );
+        assert_eq!(history.values(&var("db", "running"), 0), (None, Vec::new()));
+    }
+
+    #[test]
+    fn test_process_samples() {
+        let process = |name: &str, cmd: &str, cpu: f32, mem: u64| ProcessInfo {
+            pid: 1,
+            name: name.to_string(),
+            runtime: 0,
+            cpu,
+            mem,
+            stat: "Run".to_string(),
+            cmd: cmd.to_string(),
+            env: String::new(),
+        };
+        let processes = [
+            process("postgres", "postgres -D /var/lib/postgresql", 3.0, 100),
+            process("postgres", "postgres: checkpointer", 12.5, 40),
+            process(
+                "python3",
+                "python3 /srv/app/worker.py --queue jobs",
+                50.0,
+                300,
+            ),
+        ];
+        let patterns = HashSet::from(["postgres", "re:worker\\.py", "nginx", "re:("]);
+        let values = process_samples(&processes, &patterns);
+        let get = |pattern: &str, var: &str| {
+            values
+                .get(&("proc".to_string(), pattern.to_string(), var.to_string()))
+                .copied()
+        };
+
+        assert_eq!(get("postgres", "count"), Some(2.0));
+        assert_eq!(get("postgres", "cpu_usage"), Some(12.5));
+        assert_eq!(get("postgres", "mem"), Some(100.0));
+        assert_eq!(get("re:worker\\.py", "count"), Some(1.0));
+        // Not running is a count of 0 without usage
+        assert_eq!(get("nginx", "count"), Some(0.0));
+        assert_eq!(get("nginx", "cpu_usage"), None);
+        // Invalid regexes are skipped
+        assert_eq!(get("re:(", "count"), None);
+        assert!(ProcessMatcher::new("re:(").is_err());
+    }
+
+    #[test]
+    fn test_queue_notifications() {
+        let db = Database::new(":memory:").unwrap();
+        let hub = Hub::new();
+        let exec = |id: &str, grouping, digest_interval| NotificationMethod {
+            id: id.to_string(),
+            name: id.to_string(),
+            grouping,
+            digest_interval,
+            ..method("exec")
+        };
+        let method_map: HashMap<String, NotificationMethod> = [
+            exec("plain", false, None),
+            exec("grouped", true, None),
+            exec("digest", false, Some(10)),
+        ]
+        .into_iter()
+        .map(|m| {
+            db.save_notification_method(&m).unwrap();
+            (m.id.clone(), m)
+        })
+        .collect();
+        let queued = |method_id: &str| QueuedNotification {
+            id: 0,
+            event_id: None,
+            method_id: method_id.to_string(),
+            template: None,
+            context: serde_json::Value::Null,
+            attempts: 0,
+            next_attempt: 0,
+            batch: None,
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        for cycle in [1, 2] {
+            let queue = ["plain", "grouped", "digest", "grouped", "digest"]
+                .into_iter()
+                .map(queued)
+                .collect();
+            queue_notifications(&db, &hub, &method_map, queue, cycle);
+        }
+
+        let mut queued = db.due_notifications(i64::MAX).unwrap();
+        queued.sort_by_key(|n| n.id);
+        let batches = |method_id: &str| -> Vec<Option<String>> {
+            queued
+                .iter()
+                .filter(|n| n.method_id == method_id)
+                .map(|n| n.batch.clone())
+                .collect()
+        };
+        assert_eq!(batches("plain"), [None, None]);
+        let check = |cycle: i64| Some(format!("check:{}", cycle));
+        assert_eq!(batches("grouped"), [check(1), check(1), check(2), check(2)]);
+        // Both checks end up in the digest sent 10 minutes after the first one
+        let digest = batches("digest");
+        assert_eq!(digest.len(), 4);
+        assert!(digest.iter().all(|batch| *batch == digest[0]));
+        let send_at = queued.iter().filter(|n| n.method_id == "digest");
+        assert!(send_at.map(|n| n.next_attempt).all(|t| t >= now + 600));
+        assert_eq!(db.due_notifications(now + 60).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_save_keeps_alert_state() {
+        let db = Database::new(":memory:").unwrap();
+        let mut a = alert(">", 90.0, None);
+        db.save_alert(&a).unwrap();
+        a.firing = true;
+        a.fired_at = Some(100);
+        a.last_notified = Some(200);
+        a.escalation_level = 1;
+        db.set_alert_state(&a).unwrap();
+        // As sent by the alerts form
+        let mut edited = alert(">", 90.0, None);
+        edited.name = "CPU".to_string();
+        assert!(!condition_changed(&a, &edited));
+        db.save_alert(&edited).unwrap();
+        let saved = db.get_alerts().unwrap().pop().unwrap();
+        assert_eq!(saved.name, "CPU");
+        assert!(saved.firing);
+        assert_eq!(
+            (saved.fired_at, saved.last_notified),
+            (Some(100), Some(200))
+        );
+        assert_eq!(saved.escalation_level, 1);
+
+        // Raising the threshold resolves it instead
+        edited.threshold = 95.0;
+        assert!(condition_changed(&saved, &edited));
+        resolve_edited_alert(&db, &Hub::new(), saved).unwrap();
+        db.save_alert(&edited).unwrap();
+        let saved = db.get_alerts().unwrap().pop().unwrap();
+        assert!(!saved.firing);
+        assert_eq!((saved.fired_at, saved.escalation_level), (None, 0));
+        let events = db
+            .query_alert_events(&AlertHistoryQuery {
+                start_time: None,
+                end_time: None,
+                alert_id: Some("a".to_string()),
+                limit: None,
+            })
+            .unwrap();
+        assert_eq!(events[0].transition, "resolved");
+
+        edited.enabled = false;
+        assert!(condition_changed(&saved, &edited));
+    }
+
+    #[test]
+    fn test_escalations() {
+        let mut a = alert(">", 90.0, None);
+        a.notif_methods = vec!["m1".to_string()];
+        a.escalations = vec![
+            AlertEscalation {
+                after: 15,
+                notif_methods: vec!["m1".to_string(), "m2".to_string()],
+            },
+            AlertEscalation {
+                after: 60,
+                notif_methods: vec!["m3".to_string()],
+            },
+        ];
+        assert!(validate_alert(&a).is_ok());
+        assert_eq!(notified_methods(&a), vec!["m1"]);
+        a.escalation_level = 1;
+        assert_eq!(notified_methods(&a), vec!["m1", "m2"]);
+        a.escalation_level = 2;
+        assert_eq!(notified_methods(&a), vec!["m1", "m2", "m3"]);
+
+        a.escalations.swap(0, 1);
+        assert!(validate_alert(&a).is_err());
+        a.escalations.clear();
+        a.renotify_interval = Some(0);
+        assert!(validate_alert(&a).is_err());
+    }
+}
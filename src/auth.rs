@@ -91,6 +91,38 @@ async fn auth_middleware(
         return Ok(next.run(request).await);
     }
 
+    // Scrapers can't follow the login flow, so answer them with 401 instead of a redirect
+    let is_metrics = request.uri().path() == "/metrics";
+    let unauthorized = || {
+        if is_metrics {
+            StatusCode::UNAUTHORIZED.into_response()
+        } else {
+            Redirect::temporary("./auth").into_response()
+        }
+    };
+
+    // Allow the metrics endpoint to be accessed with the configured bearer token
+    if is_metrics && let Some(ref metrics_token) = config.metrics_token {
+        let bearer = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        if tokens_match(bearer.trim(), metrics_token) {
+            return Ok(next.run(request).await);
+        }
+    }
+
+    // Without a password only the metrics token is enforced
+    if config.password_hash.is_none() {
+        if is_metrics {
+            return Ok(unauthorized());
+        }
+        return Ok(next.run(request).await);
+    }
+
     // Extract JWT token from cookie
     let token = match request.headers().get("cookie") {
         Some(cookie) => {
@@ -105,7 +137,7 @@ async fn auth_middleware(
 
             token.to_string()
         }
-        None => return Ok(Redirect::temporary("./auth").into_response()),
+        None => return Ok(unauthorized()),
     };
 
     // Verify JWT token
@@ -115,7 +147,7 @@ async fn auth_middleware(
         &jsonwebtoken::Validation::default(),
     ) {
         Ok(data) => data.claims,
-        Err(_) => return Ok(Redirect::temporary("./auth").into_response()),
+        Err(_) => return Ok(unauthorized()),
     };
 
     // Check if token is expired
@@ -124,12 +156,22 @@ async fn auth_middleware(
         .unwrap()
         .as_secs() as usize;
     if token_data.exp < now {
-        return Ok(Redirect::temporary("./auth").into_response());
+        return Ok(unauthorized());
     }
 
     Ok(next.run(request).await)
 }
 
+/// Compare two tokens without short-circuiting on the first mismatching byte
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 pub fn apply_auth_middleware(app: Router, config: Arc<Config>) -> Router {
     app.layer(middleware::from_fn_with_state(config, auth_middleware))
 }
@@ -349,8 +349,9 @@ pub async fn get_docker_containers() -> Option<DockerInfo> {
 
     let mut result = Vec::with_capacity(containers.len());
 
-    // Pre-collect all stats futures
+    // Pre-collect all stats futures, along with the inspection for restarts and health
     debug!("Gathering stats for {} containers", containers.len());
+    let docker = &docker;
     let stats_futures = containers
         .iter()
         .map(|container| {
@@ -363,15 +364,28 @@ pub async fn get_docker_containers() -> Option<DockerInfo> {
                     ..Default::default()
                 }),
             );
-            async move { (container.clone(), stats_stream.next().await) }
+            async move {
+                let (stats, inspect) = futures::join!(
+                    stats_stream.next(),
+                    docker.inspect_container(&container_id, None)
+                );
+                (container.clone(), stats, inspect)
+            }
         })
         .collect::<Vec<_>>();
 
     // Resolve all futures in parallel
     let results = futures::future::join_all(stats_futures).await;
 
-    for (container, stats_result) in results {
+    for (container, stats_result, inspect_result) in results {
         let container_id = container.id.clone().unwrap_or_default();
+        let (restart_count, state) = match inspect_result {
+            Ok(inspect) => (inspect.restart_count.unwrap_or(0), inspect.state),
+            Err(e) => {
+                warn!("Failed to inspect container {}: {}", container_id, e);
+                (0, None)
+            }
+        };
 
         // Get container stats
         let stats = match stats_result {
@@ -445,6 +459,13 @@ pub async fn get_docker_containers() -> Option<DockerInfo> {
                 .unwrap_or(bollard::models::ContainerSummaryStateEnum::EMPTY)
                 .to_string(),
             created: container.created.unwrap_or(0),
+            restart_count,
+            health: state
+                .as_ref()
+                .and_then(|s| s.health.as_ref())
+                .and_then(|h| h.status)
+                .map(|status| status.to_string())
+                .unwrap_or_default(),
             ports,
             cpu_usage,
             mem_usage: stats
@@ -515,8 +536,21 @@ mod tests {
             password_hash: None,
             jwt_secret: "".to_string(),
             update_interval: 60,
+            alert_interval: 60,
             system_capabilities: SystemCapabilities::default(),
             upload_limit: 10737418240,
+            metrics_token: None,
+            retention_second: 3600,
+            retention_minute: 345600,
+            retention_hour: 31536000,
+            retention_day: 0,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
+            mqtt_tls: false,
+            mqtt_topic: "simon".to_string(),
+            mqtt_interval: 30,
+            mqtt_discovery_prefix: "homeassistant".to_string(),
         };
         let capabilities = detect_system_capabilities(&config).await;
 
@@ -7,6 +7,7 @@ use std::net::{IpAddr, SocketAddr};
 
 use crate::db;
 use crate::models;
+use crate::mqtt;
 
 #[derive(Parser, Debug, Clone, Serialize, Deserialize)]
 #[command(version, about = "Simon - Simple Monitor")]
@@ -23,6 +24,10 @@ pub struct Config {
     #[arg(short = 'T', long, default_value = "2", value_parser = clap::value_parser!(u64).range(1..=30), env = "SIMON_UPDATE_INTERVAL")]
     pub update_interval: u64,
 
+    /// Alert check interval in seconds
+    #[arg(long, default_value = "60", value_parser = clap::value_parser!(u64).range(1..=3600), env = "SIMON_ALERT_INTERVAL")]
+    pub alert_interval: u64,
+
     /// Authentication password bcrypt hash.
     /// If provided, authentication will be required. Leave empty to disable authentication.
     #[arg(short = 'H', long, env = "SIMON_PASSWORD_HASH")]
@@ -41,6 +46,60 @@ pub struct Config {
     #[arg(long, default_value = "10737418240", env = "SIMON_UPLOAD_LIMIT")]
     pub upload_limit: u64,
 
+    /// Bearer token for the /metrics endpoint.
+    /// If provided, scrapers can authenticate with "Authorization: Bearer <token>" instead of a login cookie.
+    #[arg(long, env = "SIMON_METRICS_TOKEN")]
+    pub metrics_token: Option<String>,
+
+    /// How long per-second data is kept (e.g. "90m", "1h", "2d"), 0 keeps it forever
+    #[arg(long, default_value = "1h", value_parser = parse_retention, env = "SIMON_RETENTION_SECOND")]
+    pub retention_second: u64,
+
+    /// How long per-minute data is kept, 0 keeps it forever
+    #[arg(long, default_value = "4d", value_parser = parse_retention, env = "SIMON_RETENTION_MINUTE")]
+    pub retention_minute: u64,
+
+    /// How long per-hour data is kept, 0 keeps it forever
+    #[arg(long, default_value = "365d", value_parser = parse_retention, env = "SIMON_RETENTION_HOUR")]
+    pub retention_hour: u64,
+
+    /// How long per-day data is kept, 0 keeps it forever
+    #[arg(long, default_value = "0", value_parser = parse_retention, env = "SIMON_RETENTION_DAY")]
+    pub retention_day: u64,
+
+    /// MQTT broker to publish metrics and alert changes to, as "host[:port]".
+    /// If empty, nothing is published.
+    #[arg(long, env = "SIMON_MQTT_BROKER")]
+    pub mqtt_broker: Option<String>,
+
+    /// Username for the MQTT broker, no authentication when empty
+    #[arg(long, env = "SIMON_MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+
+    /// Password for the MQTT broker
+    #[arg(long, env = "SIMON_MQTT_PASSWORD")]
+    pub mqtt_password: Option<String>,
+
+    /// Connect to the MQTT broker over TLS
+    #[arg(long, env = "SIMON_MQTT_TLS")]
+    pub mqtt_tls: bool,
+
+    /// Base topic, metrics are published under "<topic>/<host name>"
+    #[arg(long, default_value = "simon", env = "SIMON_MQTT_TOPIC")]
+    pub mqtt_topic: String,
+
+    /// Interval in seconds between published metric snapshots
+    #[arg(long, default_value = "30", value_parser = clap::value_parser!(u64).range(1..=3600), env = "SIMON_MQTT_INTERVAL")]
+    pub mqtt_interval: u64,
+
+    /// Topic prefix of Home Assistant discovery messages, empty disables them
+    #[arg(
+        long,
+        default_value = "homeassistant",
+        env = "SIMON_MQTT_DISCOVERY_PREFIX"
+    )]
+    pub mqtt_discovery_prefix: String,
+
     /// JWT secret key for authentication tokens
     #[arg(skip)]
     pub jwt_secret: String,
@@ -53,6 +112,39 @@ impl Config {
     pub fn socket_address(&self) -> SocketAddr {
         SocketAddr::new(self.address, self.port)
     }
+
+    pub fn retention(&self) -> db::Retention {
+        db::Retention {
+            second: self.retention_second,
+            minute: self.retention_minute,
+            hour: self.retention_hour,
+            day: self.retention_day,
+        }
+    }
+}
+
+/// Parse a retention period like "90m", "36h" or "30d" into seconds.
+/// Plain numbers are seconds, and 0 means the data is kept forever.
+fn parse_retention(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid retention period: {}", value))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return Err(format!("unknown unit \"{}\", use s, m, h, d or w", unit)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("retention period too long: {}", value))
 }
 
 pub fn parse_config() -> Config {
@@ -77,6 +169,38 @@ pub fn parse_config() -> Config {
         }
     }
 
+    // Each resolution is aggregated from the one below it, so the finer data
+    // has to outlive at least one full bucket of the coarser table
+    if let Err(e) = config.retention().validate() {
+        error!("Invalid retention policy: {}", e);
+        std::process::exit(1);
+    }
+
+    // An empty token would match any request with an empty bearer, treat it as unset
+    if config.metrics_token.as_deref() == Some("") {
+        config.metrics_token = None;
+    }
+
+    if config
+        .mqtt_broker
+        .as_deref()
+        .is_some_and(|b| b.trim().is_empty())
+    {
+        config.mqtt_broker = None;
+    }
+    if let Some(broker) = &config.mqtt_broker
+        && let Err(e) = mqtt::parse_broker(broker)
+    {
+        error!("Invalid MQTT broker: {}", e);
+        std::process::exit(1);
+    }
+    let topic = config.mqtt_topic.trim_matches('/');
+    if topic.is_empty() || topic.contains(['+', '#']) {
+        error!("Invalid MQTT topic \"{}\"", config.mqtt_topic);
+        std::process::exit(1);
+    }
+    config.mqtt_topic = topic.to_string();
+
     let db = match db::Database::new(&config.db_path) {
         Ok(db) => db,
         Err(e) => {
@@ -1,22 +1,322 @@
 use crate::models::{
-    ALERT_VARIABLES, AlertVar, GeneralInfo, HistoricalQueryOptions, HistoricalSeries,
+    ALERT_VARIABLES, Alert, AlertDelivery, AlertEvent, AlertHistoryQuery, AlertVar, DbStats,
+    DeliveryLogEntry, DeliveryLogQuery, GeneralInfo, HistoricalQueryOptions, HistoricalSeries,
+    NotificationMethod, QueuedNotification, Silence, TableStats,
 };
-use log::error;
-use rusqlite::{Connection, Result, params};
+use log::{error, warn};
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::{Connection, OptionalExtension, Result, Row, params};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use sysinfo::System;
 
 use crate::collect_info::collect_general_info;
+use crate::migrations;
 
 const STORE_INTERVAL: u64 = 2;
+
+/// Gauges of each category, rolled up with their min, max and p95 next to the average
+fn rollup_metrics(cat: &str) -> &'static [&'static str] {
+    match cat {
+        "general" => &[
+            "cpu_usage",
+            "mem_usage",
+            "swap_usage",
+            "load_avg_1",
+            "load_avg_5",
+            "load_avg_15",
+        ],
+        "net" => &["rx_rate", "tx_rate"],
+        _ => &["read_rate", "write_rate", "disk_usage"],
+    }
+}
+
+/// Cumulative counters of each category, rolled up with their latest (largest) value
+fn rollup_counters(cat: &str) -> &'static [&'static str] {
+    match cat {
+        "general" => &[],
+        "net" => &["rx", "tx"],
+        _ => &["total_read", "total_write"],
+    }
+}
+
+/// Build the statement aggregating the rows of `{cat}_{from}` in [?1, ?2)
+/// into `{cat}_{to}` rows stamped with ?2
+fn rollup_query(cat: &str, from: &str, to: &str) -> String {
+    let mut columns = vec!["timestamp".to_string()];
+    let mut values = vec!["?2".to_string()];
+    if cat != "general" {
+        columns.push("name".to_string());
+        values.push("name".to_string());
+    }
+    for counter in rollup_counters(cat) {
+        columns.push(counter.to_string());
+        values.push(format!("MAX({})", counter));
+    }
+    for metric in rollup_metrics(cat) {
+        // Rates are whole bytes per second, percentages and loads keep two decimals
+        let digits = if metric.ends_with("_rate") { 0 } else { 2 };
+        // Per-second rows have no statistics of their own.
+        // The p95 of coarser tables is taken over the finer p95 values, which is an approximation.
+        let stat_source = |stat: &str| {
+            if from == "s" {
+                metric.to_string()
+            } else {
+                format!("COALESCE({}_{}, {})", metric, stat, metric)
+            }
+        };
+        columns.extend([
+            metric.to_string(),
+            format!("{}_min", metric),
+            format!("{}_max", metric),
+            format!("{}_p95", metric),
+        ]);
+        values.extend([
+            format!("round(AVG({}), {})", metric, digits),
+            format!("round(MIN({}), {})", stat_source("min"), digits),
+            format!("round(MAX({}), {})", stat_source("max"), digits),
+            format!("round(p95({}), {})", stat_source("p95"), digits),
+        ]);
+    }
+    let group_by = if cat == "general" {
+        ""
+    } else {
+        " GROUP BY name"
+    };
+    format!(
+        "INSERT INTO {cat}_{to} ({}) SELECT {} FROM {cat}_{from} WHERE timestamp >= ?1 AND timestamp < ?2{group_by}",
+        columns.join(", "),
+        values.join(", ")
+    )
+}
+
+/// Source table, target table and bucket size in seconds of each rollup, finest first
+const ROLLUPS: [(&str, &str, i64); 3] = [("s", "m", 60), ("m", "h", 3600), ("h", "d", 86400)];
+
+/// Aggregate every complete bucket that hasn't been rolled up yet.
+/// Buckets are stamped with their end and cover [end - size, end) of the finer table,
+/// the end of the last one written is kept per table in `rollup_state`.
+fn run_rollups(conn: &Connection, now: i64) -> Result<()> {
+    for (from, to, size) in ROLLUPS {
+        // Only buckets that ended before now are complete
+        let latest = now - now.rem_euclid(size);
+        for cat in ["general", "net", "disk"] {
+            let table = format!("{}_{}", cat, to);
+            let source = format!("{}_{}", cat, from);
+            let last: Option<i64> = conn
+                .query_row(
+                    "SELECT last_bucket FROM rollup_state WHERE tbl = ?",
+                    params![table],
+                    |row| row.get(0),
+                )
+                .ok();
+            let mut last = match last {
+                Some(last) if last > latest + size => {
+                    // The clock went backwards, start over from now instead of waiting for it to catch up.
+                    // Buckets produced again later replace the ones written before the jump.
+                    warn!(
+                        "Last rollup of {} is in the future ({} > {}), clock moved backwards",
+                        table, last, now
+                    );
+                    conn.execute(
+                        "INSERT OR REPLACE INTO rollup_state (tbl, last_bucket) VALUES (?, ?)",
+                        params![table, latest],
+                    )?;
+                    latest
+                }
+                Some(last) => last,
+                None => {
+                    // Resume after the rows written before the state was tracked,
+                    // or before the first bucket with source data
+                    let newest: Option<i64> = conn.query_row(
+                        &format!("SELECT MAX(timestamp) FROM {}", table),
+                        [],
+                        |row| row.get(0),
+                    )?;
+                    let oldest: Option<i64> = conn.query_row(
+                        &format!("SELECT MIN(timestamp) FROM {}", source),
+                        [],
+                        |row| row.get(0),
+                    )?;
+                    match (newest, oldest) {
+                        (Some(newest), _) => newest,
+                        (None, Some(oldest)) => oldest - oldest.rem_euclid(size),
+                        (None, None) => continue,
+                    }
+                }
+            };
+
+            while last + size <= latest {
+                // Jump over ranges without data, like the time simon wasn't running
+                let next: Option<i64> = conn.query_row(
+                    &format!("SELECT MIN(timestamp) FROM {} WHERE timestamp >= ?", source),
+                    params![last],
+                    |row| row.get(0),
+                )?;
+                let end = match next {
+                    Some(next) => next - next.rem_euclid(size) + size,
+                    None => latest + size,
+                };
+                if end > latest {
+                    // Nothing left but the bucket in progress
+                    last = latest;
+                } else {
+                    rollup_bucket(conn, cat, from, to, end - size, end)?;
+                    last = end;
+                }
+                conn.execute(
+                    "INSERT OR REPLACE INTO rollup_state (tbl, last_bucket) VALUES (?, ?)",
+                    params![table, last],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a single bucket, replacing it if it was already produced
+fn rollup_bucket(
+    conn: &Connection,
+    cat: &str,
+    from: &str,
+    to: &str,
+    start: i64,
+    end: i64,
+) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        &format!("DELETE FROM {}_{} WHERE timestamp = ?", cat, to),
+        params![end],
+    )?;
+    tx.execute(&rollup_query(cat, from, to), params![start, end])?;
+    tx.commit()
+}
+
+/// Percentile aggregate (nearest-rank method) ignoring NULL values
+struct Percentile(f64);
+
+impl Aggregate<Vec<f64>, Option<f64>> for Percentile {
+    fn init(&self, _: &mut Context<'_>) -> Result<Vec<f64>> {
+        Ok(Vec::new())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, values: &mut Vec<f64>) -> Result<()> {
+        if let Some(value) = ctx.get::<Option<f64>>(0)? {
+            values.push(value);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, values: Option<Vec<f64>>) -> Result<Option<f64>> {
+        let mut values = match values {
+            Some(values) if !values.is_empty() => values,
+            _ => return Ok(None),
+        };
+        values.sort_by(f64::total_cmp);
+        let rank = (self.0 / 100.0 * values.len() as f64).ceil() as usize;
+        Ok(Some(values[rank.clamp(1, values.len()) - 1]))
+    }
+}
+
+/// How long the rows of each resolution are kept, in seconds. 0 keeps them forever.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub second: u64,
+    pub minute: u64,
+    pub hour: u64,
+    pub day: u64,
+}
+
+impl Retention {
+    /// Retention of the tables with the given resolution suffix ("s", "m", "h" or "d")
+    pub fn for_suffix(&self, suffix: &str) -> u64 {
+        match suffix {
+            "s" => self.second,
+            "m" => self.minute,
+            "h" => self.hour,
+            _ => self.day,
+        }
+    }
+
+    /// Make sure every resolution keeps enough data to aggregate the next one
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        for (name, value, min) in [
+            ("second", self.second, 120),
+            ("minute", self.minute, 7200),
+            ("hour", self.hour, 2 * 86400),
+        ] {
+            if value != 0 && value < min {
+                return Err(format!(
+                    "{} data must be kept for at least {} seconds, got {}",
+                    name, min, value
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum DbError {
+    Sqlite(rusqlite::Error),
+    /// The database was upgraded by a newer version of simon
+    NewerSchema {
+        found: u32,
+        supported: u32,
+    },
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::Sqlite(e) => write!(f, "{}", e),
+            DbError::NewerSchema { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported version {}, upgrade simon",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        DbError::Sqlite(e)
+    }
+}
+
+/// Delivery of the first 9 columns of a row
+fn alert_delivery(row: &Row) -> Result<AlertDelivery> {
+    Ok(AlertDelivery {
+        method_id: row.get(0)?,
+        method_name: row.get(1)?,
+        t: row.get(2)?,
+        success: row.get(3)?,
+        error: row.get(4)?,
+        attempt: row.get(5)?,
+        status: row.get(6)?,
+        response: row.get(7)?,
+        retry_at: row.get(8)?,
+    })
+}
+
 pub struct Database {
     pub conn: Arc<Mutex<Connection>>,
 }
 
 impl Database {
-    pub fn new(db_path: &str) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
+    pub fn new(db_path: &str) -> std::result::Result<Self, DbError> {
+        let mut conn = Connection::open(db_path)?;
+        // Connections are opened per request, wait for the writers instead of failing
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.create_aggregate_function(
+            "p95",
+            1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            Percentile(95.0),
+        )?;
 
         // Enable Write-Ahead Logging for better concurrency and performance
         let _ = conn.query_row("PRAGMA journal_mode = WAL", [], |row| {
@@ -25,139 +325,571 @@ impl Database {
         conn.execute("PRAGMA synchronous = NORMAL", [])?;
         conn.execute("PRAGMA cache_size = 1000", [])?;
         conn.execute("PRAGMA temp_store = FILE", [])?;
+        conn.execute("PRAGMA foreign_keys = ON", [])?;
 
-        // Create tables
-        for table_name in ["general_s", "general_m", "general_h", "general_d"] {
-            conn.execute(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-                    id INTEGER PRIMARY KEY,
-                    timestamp INTEGER,
-                    cpu_usage REAL,
-                    mem_usage REAL,
-                    swap_usage REAL,
-                    load_avg_1 REAL,
-                    load_avg_5 REAL,
-                    load_avg_15 REAL
-                )",
-                    table_name
-                )
-                .as_str(),
-                [],
-            )?;
+        migrations::migrate(&mut conn)?;
+
+        Ok(Database {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn get_kv_str(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT value FROM kv WHERE key = ?")?;
+        let mut rows = stmt.query(params![key])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            _ => Ok(None),
         }
+    }
 
-        for table_name in ["net_s", "net_m", "net_h", "net_d"] {
-            conn.execute(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-                    id INTEGER PRIMARY KEY,
-                    timestamp INTEGER,
-                    name TEXT,
-                    rx REAL,
-                    tx REAL,
-                    rx_rate REAL,
-                    tx_rate REAL
-                )",
-                    table_name
-                )
-                .as_str(),
-                [],
-            )?;
+    pub fn set_kv_str(&self, key: &str, value: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO kv (key, value) VALUES (?, ?)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_alerts(&self) -> Result<Vec<Alert>> {
+        let conn = self.conn.lock().unwrap();
+        let mut links: std::collections::HashMap<String, Vec<String>> =
+            std::collections::HashMap::new();
+        let mut stmt =
+            conn.prepare("SELECT alert_id, method_id FROM alert_notif_methods ORDER BY rowid")?;
+        for link in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (alert_id, method_id): (String, String) = link?;
+            links.entry(alert_id).or_default().push(method_id);
         }
 
-        for table_name in ["disk_s", "disk_m", "disk_h", "disk_d"] {
-            conn.execute(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {} (
-                    id INTEGER PRIMARY KEY,
-                    timestamp INTEGER,
-                    name TEXT,
-                    total_read REAL,
-                    total_write REAL,
-                    read_rate REAL,
-                    write_rate REAL,
-                    disk_usage REAL
-                )",
-                    table_name
+        let mut stmt = conn.prepare(
+            "SELECT id, cat, var, resrc, threshold, operator, time_window, enabled, firing,
+                recovery_threshold, kind, horizon, condition, renotify_interval, escalations,
+                fired_at, last_notified, escalation_level, resolution, name, template
+             FROM alerts ORDER BY rowid",
+        )?;
+        let alerts = stmt.query_map([], |row| {
+            let id: String = row.get(0)?;
+            let json_column = |idx: usize, e: serde_json::Error| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    idx,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
                 )
-                .as_str(),
-                [],
+            };
+            let condition = match row.get::<_, Option<String>>(12)? {
+                Some(condition) => {
+                    Some(serde_json::from_str(&condition).map_err(|e| json_column(12, e))?)
+                }
+                None => None,
+            };
+            let escalations: String = row.get(14)?;
+            Ok(Alert {
+                notif_methods: links.remove(&id).unwrap_or_default(),
+                id,
+                var: AlertVar {
+                    cat: row.get(1)?,
+                    var: row.get(2)?,
+                    resrc: row.get(3)?,
+                },
+                threshold: row.get(4)?,
+                operator: row.get(5)?,
+                time_window: row.get(6)?,
+                enabled: row.get(7)?,
+                firing: row.get(8)?,
+                recovery_threshold: row.get(9)?,
+                kind: row.get(10)?,
+                horizon: row.get(11)?,
+                condition,
+                renotify_interval: row.get(13)?,
+                escalations: serde_json::from_str(&escalations).map_err(|e| json_column(14, e))?,
+                fired_at: row.get(15)?,
+                last_notified: row.get(16)?,
+                escalation_level: row.get(17)?,
+                resolution: row.get(18)?,
+                name: row.get(19)?,
+                template: row.get(20)?,
+            })
+        })?;
+        alerts.collect()
+    }
+
+    /// Insert or update an alert along with its notification methods.
+    /// Methods that don't exist are ignored. Only the configuration is saved, the state
+    /// of new alerts starts out clear and is then up to the alert loop (`set_alert_state`).
+    pub fn save_alert(&self, alert: &Alert) -> Result<()> {
+        let to_json = |e| rusqlite::Error::ToSqlConversionFailure(Box::new(e));
+        let condition = alert
+            .condition
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(to_json)?;
+        let escalations = serde_json::to_string(&alert.escalations).map_err(to_json)?;
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO alerts (
+                id, cat, var, resrc, threshold, operator, time_window, enabled, firing,
+                recovery_threshold, kind, horizon, condition, renotify_interval, escalations,
+                fired_at, last_notified, escalation_level, resolution, name, template
+             )
+             VALUES (
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 0, ?9, ?10, ?11, ?12, ?13, ?14, NULL, NULL, 0,
+                ?15, ?16, ?17
+             )
+             ON CONFLICT (id) DO UPDATE SET
+                cat = ?2, var = ?3, resrc = ?4, threshold = ?5, operator = ?6,
+                time_window = ?7, enabled = ?8, recovery_threshold = ?9, kind = ?10,
+                horizon = ?11, condition = ?12, renotify_interval = ?13, escalations = ?14,
+                resolution = ?15, name = ?16, template = ?17",
+            params![
+                alert.id,
+                alert.var.cat,
+                alert.var.var,
+                alert.var.resrc,
+                alert.threshold,
+                alert.operator,
+                alert.time_window,
+                alert.enabled,
+                alert.recovery_threshold,
+                alert.kind,
+                alert.horizon,
+                condition,
+                alert.renotify_interval,
+                escalations,
+                alert.resolution,
+                alert.name,
+                alert.template
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM alert_notif_methods WHERE alert_id = ?",
+            params![alert.id],
+        )?;
+        for method_id in &alert.notif_methods {
+            tx.execute(
+                "INSERT OR IGNORE INTO alert_notif_methods (alert_id, method_id)
+                 SELECT ?1, id FROM notification_methods WHERE id = ?2",
+                params![alert.id, method_id],
             )?;
         }
+        tx.commit()
+    }
+
+    /// Returns whether the alert existed
+    pub fn delete_alert(&self, id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM alerts WHERE id = ?", params![id])? > 0)
+    }
 
+    /// Store the state of an alert kept by the alert loop
+    pub fn set_alert_state(&self, alert: &Alert) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS kv (
-                key TEXT PRIMARY KEY,
-                value BLOB
-            )",
-            [],
+            "UPDATE alerts SET firing = ?, fired_at = ?, last_notified = ?, escalation_level = ?
+             WHERE id = ?",
+            params![
+                alert.firing,
+                alert.fired_at,
+                alert.last_notified,
+                alert.escalation_level,
+                alert.id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// All notification methods, skipping the ones whose config can't be read
+    pub fn get_notification_methods(&self) -> Result<Vec<NotificationMethod>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, name, kind, enabled, config, template, timeout, max_retries, grouping,
+                digest_interval
+             FROM notification_methods ORDER BY rowid",
         )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, bool>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, u32>(6)?,
+                row.get::<_, u32>(7)?,
+                row.get::<_, bool>(8)?,
+                row.get::<_, Option<u32>>(9)?,
+            ))
+        })?;
 
-        // Create indexes
-        // Timestamp indexes for all tables
-        for table in [
-            "general_s",
-            "general_m",
-            "general_h",
-            "general_d",
-            "net_s",
-            "net_m",
-            "net_h",
-            "net_d",
-            "disk_s",
-            "disk_m",
-            "disk_h",
-            "disk_d",
-        ] {
-            conn.execute(
-                &format!(
-                    "CREATE INDEX IF NOT EXISTS idx_{}_timestamp ON {} (timestamp)",
-                    table, table
-                ),
-                [],
-            )?;
+        let mut methods = Vec::new();
+        for row in rows {
+            let (
+                id,
+                name,
+                kind,
+                enabled,
+                config,
+                template,
+                timeout,
+                max_retries,
+                grouping,
+                digest_interval,
+            ) = row?;
+            match serde_json::from_str(&config) {
+                Ok(config) => methods.push(NotificationMethod {
+                    id,
+                    name,
+                    kind,
+                    enabled,
+                    config,
+                    template,
+                    timeout,
+                    max_retries,
+                    grouping,
+                    digest_interval,
+                }),
+                Err(e) => error!("Invalid config of notification method {}: {}", id, e),
+            }
         }
+        Ok(methods)
+    }
 
-        // Compound indexes for network and disk tables to optimize queries by name and timestamp
-        for table in [
-            "net_s", "net_m", "net_h", "net_d", "disk_s", "disk_m", "disk_h", "disk_d",
-        ] {
-            conn.execute(
-                &format!(
-                    "CREATE INDEX IF NOT EXISTS idx_{}_name_timestamp ON {} (name, timestamp)",
-                    table, table
-                ),
-                [],
-            )?;
+    pub fn save_notification_method(&self, method: &NotificationMethod) -> Result<()> {
+        let config = serde_json::to_string(&method.config)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO notification_methods
+                (id, name, kind, enabled, config, template, timeout, max_retries, grouping,
+                 digest_interval)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT (id) DO UPDATE SET
+                name = ?2, kind = ?3, enabled = ?4, config = ?5, template = ?6,
+                timeout = ?7, max_retries = ?8, grouping = ?9, digest_interval = ?10",
+            params![
+                method.id,
+                method.name,
+                method.kind,
+                method.enabled,
+                config,
+                method.template,
+                method.timeout,
+                method.max_retries,
+                method.grouping,
+                method.digest_interval
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns whether the method existed, alerts using it stop referencing it
+    pub fn delete_notification_method(&self, id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM notification_methods WHERE id = ?", params![id])? > 0)
+    }
+
+    /// Record an alert transition, returning the id of the event
+    pub fn insert_alert_event(&self, event: &AlertEvent) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO alert_events (alert_id, t, transition, value, threshold, message, silenced_by)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                event.alert_id,
+                event.t,
+                event.transition,
+                event.value,
+                event.threshold,
+                event.message,
+                event.silenced_by
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn insert_alert_delivery(&self, event_id: i64, delivery: &AlertDelivery) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO alert_event_deliveries
+                (event_id, method_id, method_name, t, success, error, attempt, status, response, retry_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                event_id,
+                delivery.method_id,
+                delivery.method_name,
+                delivery.t,
+                delivery.success,
+                delivery.error,
+                delivery.attempt,
+                delivery.status,
+                delivery.response,
+                delivery.retry_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Alert events matching the query, newest first, with their deliveries
+    pub fn query_alert_events(&self, query: &AlertHistoryQuery) -> Result<Vec<AlertEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, alert_id, t, transition, value, threshold, message, silenced_by
+             FROM alert_events
+             WHERE (?1 IS NULL OR t >= ?1) AND (?2 IS NULL OR t <= ?2) AND (?3 IS NULL OR alert_id = ?3)
+             ORDER BY t DESC, id DESC
+             LIMIT ?4",
+        )?;
+        let mut events = stmt
+            .query_map(
+                params![
+                    query.start_time,
+                    query.end_time,
+                    query.alert_id,
+                    query.limit.unwrap_or(1000)
+                ],
+                |row| {
+                    Ok(AlertEvent {
+                        id: row.get(0)?,
+                        alert_id: row.get(1)?,
+                        t: row.get(2)?,
+                        transition: row.get(3)?,
+                        value: row.get(4)?,
+                        threshold: row.get(5)?,
+                        message: row.get(6)?,
+                        silenced_by: row.get(7)?,
+                        deliveries: Vec::new(),
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT method_id, method_name, t, success, error, attempt, status, response, retry_at
+             FROM alert_event_deliveries WHERE event_id = ? ORDER BY id",
+        )?;
+        for event in &mut events {
+            event.deliveries = stmt
+                .query_map(params![event.id], alert_delivery)?
+                .collect::<Result<_>>()?;
         }
+        Ok(events)
+    }
 
-        conn.execute("CREATE INDEX IF NOT EXISTS idx_kv_key ON kv (key)", [])?;
+    /// Delivery attempts matching the query, newest first
+    pub fn query_deliveries(&self, query: &DeliveryLogQuery) -> Result<Vec<DeliveryLogEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT d.method_id, d.method_name, d.t, d.success, d.error, d.attempt, d.status,
+                    d.response, d.retry_at, d.event_id, e.alert_id, e.transition
+             FROM alert_event_deliveries d JOIN alert_events e ON e.id = d.event_id
+             WHERE (?1 IS NULL OR d.t >= ?1) AND (?2 IS NULL OR d.t <= ?2)
+                AND (?3 IS NULL OR d.method_id = ?3) AND (?4 IS NULL OR e.alert_id = ?4)
+                AND (?5 IS NULL OR d.success = ?5)
+             ORDER BY d.t DESC, d.id DESC
+             LIMIT ?6",
+        )?;
+        stmt.query_map(
+            params![
+                query.start_time,
+                query.end_time,
+                query.method_id,
+                query.alert_id,
+                query.success,
+                query.limit.unwrap_or(1000)
+            ],
+            |row| {
+                Ok(DeliveryLogEntry {
+                    event_id: row.get(9)?,
+                    alert_id: row.get(10)?,
+                    transition: row.get(11)?,
+                    delivery: alert_delivery(row)?,
+                })
+            },
+        )?
+        .collect()
+    }
 
-        Ok(Database {
-            conn: Arc::new(Mutex::new(conn)),
-        })
+    /// Queue notifications all at once, so a batch is never picked up half queued
+    pub fn enqueue_notifications(&self, notifications: &[QueuedNotification]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for notification in notifications {
+            tx.execute(
+                "INSERT INTO notification_queue
+                    (event_id, method_id, template, context, attempts, next_attempt, batch)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    notification.event_id,
+                    notification.method_id,
+                    notification.template,
+                    notification.context.to_string(),
+                    notification.attempts,
+                    notification.next_attempt,
+                    notification.batch
+                ],
+            )?;
+        }
+        tx.commit()
     }
 
-    pub fn get_kv_str(&self, key: &str) -> Result<Option<String>> {
+    /// Batch and send time of the digest of a method still collecting notifications, if any
+    pub fn open_digest(&self, method_id: &str, now: i64) -> Result<Option<(String, i64)>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT value FROM kv WHERE key = ?")?;
-        let mut rows = stmt.query(params![key])?;
-        match rows.next()? {
-            Some(row) => Ok(Some(row.get(0)?)),
-            _ => Ok(None),
+        conn.query_row(
+            "SELECT batch, next_attempt FROM notification_queue
+             WHERE method_id = ? AND batch LIKE 'digest:%' AND attempts = 0 AND next_attempt > ?
+             ORDER BY id LIMIT 1",
+            params![method_id, now],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+    }
+
+    /// Queued notifications due by `now`, oldest first.
+    /// Ones whose context can't be read are dropped, as they would never be sent.
+    pub fn due_notifications(&self, now: i64) -> Result<Vec<QueuedNotification>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, event_id, method_id, template, context, attempts, next_attempt, batch
+             FROM notification_queue WHERE next_attempt <= ? ORDER BY next_attempt, id",
+        )?;
+        let rows = stmt.query_map(params![now], |row| {
+            Ok((
+                QueuedNotification {
+                    id: row.get(0)?,
+                    event_id: row.get(1)?,
+                    method_id: row.get(2)?,
+                    template: row.get(3)?,
+                    context: serde_json::Value::Null,
+                    attempts: row.get(5)?,
+                    next_attempt: row.get(6)?,
+                    batch: row.get(7)?,
+                },
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+
+        let mut notifications = Vec::new();
+        let mut unreadable = Vec::new();
+        for row in rows {
+            let (mut notification, context) = row?;
+            match serde_json::from_str(&context) {
+                Ok(context) => {
+                    notification.context = context;
+                    notifications.push(notification);
+                }
+                Err(e) => {
+                    error!(
+                        "Dropping queued notification {} to {} with an invalid context: {}",
+                        notification.id, notification.method_id, e
+                    );
+                    unreadable.push(notification.id);
+                }
+            }
+        }
+        drop(stmt);
+        for id in unreadable {
+            conn.execute("DELETE FROM notification_queue WHERE id = ?", params![id])?;
         }
+        Ok(notifications)
     }
 
-    pub fn set_kv_str(&self, key: &str, value: &str) -> Result<()> {
+    /// When the next queued notification is due, if any
+    pub fn next_notification_time(&self) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT MIN(next_attempt) FROM notification_queue",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn reschedule_notification(&self, id: i64, attempts: u32, next_attempt: i64) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT OR REPLACE INTO kv (key, value) VALUES (?, ?)",
-            params![key, value],
+            "UPDATE notification_queue SET attempts = ?, next_attempt = ? WHERE id = ?",
+            params![attempts, next_attempt, id],
         )?;
         Ok(())
     }
 
+    pub fn dequeue_notification(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM notification_queue WHERE id = ?", params![id])?;
+        Ok(())
+    }
+
+    pub fn get_silences(&self) -> Result<Vec<Silence>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, comment, alert_ids, cat, resrc, start_time, end_time, recurrence
+             FROM silences ORDER BY start_time, id",
+        )?;
+        let json_column = |idx: usize, e: serde_json::Error| {
+            rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+        };
+        let silences = stmt.query_map([], |row| {
+            let alert_ids: String = row.get(2)?;
+            let recurrence = match row.get::<_, Option<String>>(7)? {
+                Some(recurrence) => {
+                    Some(serde_json::from_str(&recurrence).map_err(|e| json_column(7, e))?)
+                }
+                None => None,
+            };
+            Ok(Silence {
+                id: row.get(0)?,
+                comment: row.get(1)?,
+                alert_ids: serde_json::from_str(&alert_ids).map_err(|e| json_column(2, e))?,
+                cat: row.get(3)?,
+                resrc: row.get(4)?,
+                start_time: row.get(5)?,
+                end_time: row.get(6)?,
+                recurrence,
+            })
+        })?;
+        silences.collect()
+    }
+
+    pub fn save_silence(&self, silence: &Silence) -> Result<()> {
+        let to_json = |e| rusqlite::Error::ToSqlConversionFailure(Box::new(e));
+        let alert_ids = serde_json::to_string(&silence.alert_ids).map_err(to_json)?;
+        let recurrence = silence
+            .recurrence
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(to_json)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO silences (id, comment, alert_ids, cat, resrc, start_time, end_time, recurrence)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT (id) DO UPDATE SET
+                comment = ?2, alert_ids = ?3, cat = ?4, resrc = ?5,
+                start_time = ?6, end_time = ?7, recurrence = ?8",
+            params![
+                silence.id,
+                silence.comment,
+                alert_ids,
+                silence.cat,
+                silence.resrc,
+                silence.start_time,
+                silence.end_time,
+                recurrence
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns whether the silence existed
+    pub fn delete_silence(&self, id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM silences WHERE id = ?", params![id])? > 0)
+    }
+
     pub fn query_historical_data(
         &self,
         options: &HistoricalQueryOptions,
@@ -170,12 +902,44 @@ impl Database {
             _ => "m", // Default to minute metrics
         };
 
+        // Per-second tables only have the raw values
+        let agg = match options.agg.as_deref() {
+            Some("min") => "min",
+            Some("max") => "max",
+            Some("p95") => "p95",
+            _ => "avg",
+        };
+
         let mut series_results: Vec<HistoricalSeries> = Vec::with_capacity(3);
 
         for cat in ["general", "net", "disk"] {
             let table_name = format!("{}_{}", cat, resolution);
+            let metrics = rollup_metrics(cat);
+            let columns: Vec<String> = metrics
+                .iter()
+                .map(|metric| {
+                    if agg == "avg" || resolution == "s" {
+                        metric.to_string()
+                    } else {
+                        // Rows rolled up before the statistics existed only have the average
+                        format!("COALESCE({}_{}, {})", metric, agg, metric)
+                    }
+                })
+                .collect();
             // Build the query
-            let mut query = format!("SELECT * FROM {}", table_name);
+            let mut query = if cat == "general" {
+                format!(
+                    "SELECT timestamp, {} FROM {}",
+                    columns.join(", "),
+                    table_name
+                )
+            } else {
+                format!(
+                    "SELECT timestamp, name, {} FROM {}",
+                    columns.join(", "),
+                    table_name
+                )
+            };
             let mut query_params: Vec<Box<dyn rusqlite::ToSql>> = Vec::with_capacity(3);
 
             // Fix query construction - first condition shouldn't have AND
@@ -215,10 +979,6 @@ impl Database {
                 }
             };
 
-            // Get column names
-            let column_names: Vec<String> =
-                stmt.column_names().iter().map(|s| s.to_string()).collect();
-
             // Create parameter references
             let param_refs: Vec<&dyn rusqlite::ToSql> =
                 query_params.iter().map(|p| p.as_ref()).collect();
@@ -234,53 +994,31 @@ impl Database {
 
             // Process rows
             while let Some(row) = rows.next().ok().flatten() {
-                let timestamp: i64 = row.get(1)?; // timestamp is always index 1
-
-                if cat == "general" {
-                    // General tables have no name column, use "system" as name
-                    for (idx, col_name) in column_names.iter().enumerate().skip(2) {
-                        // Skip id and timestamp
-                        if let Ok(value) = row.get::<_, f64>(idx) {
-                            let key = (col_name.clone(), "system".to_string());
-                            let entry = series_map.entry(key).or_insert_with(|| HistoricalSeries {
-                                cat: cat.to_string(),
-                                stype: col_name.clone(),
-                                name: "system".to_string(),
-                                timestamps: Vec::new(),
-                                values: Vec::new(),
-                            });
-
-                            entry.timestamps.push(timestamp);
-                            entry.values.push(value);
-                        }
-                    }
+                let timestamp: i64 = row.get(0)?;
+
+                // General tables have no name column, use "system" as name
+                let (name, first_value) = if cat == "general" {
+                    ("system".to_string(), 1)
                 } else {
-                    // Net and disk tables have name column at index 2
-                    let name: String = match row.get(2) {
-                        Ok(name) => name,
+                    match row.get(1) {
+                        Ok(name) => (name, 2),
                         Err(_) => continue, // Skip if name can't be retrieved
-                    };
-
-                    for (idx, col_name) in column_names.iter().enumerate().skip(3) {
-                        // skip(3) to skip id, timestamp, name
-                        if ["rx", "tx", "total_read", "total_write"].contains(&col_name.as_str()) {
-                            // Skip total read/write columns
-                            continue;
-                        }
-                        // Skip id, timestamp, name
-                        if let Ok(value) = row.get::<_, f64>(idx) {
-                            let key = (col_name.clone(), name.clone());
-                            let entry = series_map.entry(key).or_insert_with(|| HistoricalSeries {
-                                cat: cat.to_string(),
-                                stype: col_name.clone(),
-                                name: name.clone(),
-                                timestamps: Vec::new(),
-                                values: Vec::new(),
-                            });
-
-                            entry.timestamps.push(timestamp);
-                            entry.values.push(value);
-                        }
+                    }
+                };
+
+                for (idx, metric) in metrics.iter().enumerate() {
+                    if let Ok(value) = row.get::<_, f64>(first_value + idx) {
+                        let key = (metric.to_string(), name.clone());
+                        let entry = series_map.entry(key).or_insert_with(|| HistoricalSeries {
+                            cat: cat.to_string(),
+                            stype: metric.to_string(),
+                            name: name.clone(),
+                            timestamps: Vec::new(),
+                            values: Vec::new(),
+                        });
+
+                        entry.timestamps.push(timestamp);
+                        entry.values.push(value);
                     }
                 }
             }
@@ -291,6 +1029,48 @@ impl Database {
         Ok(series_results)
     }
 
+    /// Row count, oldest timestamp and on-disk size of every time-series table
+    pub fn get_db_stats(&self, retention: &Retention) -> Result<DbStats> {
+        let conn = self.conn.lock().unwrap();
+        let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        let page_count: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let free_pages: i64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+
+        let mut tables = Vec::with_capacity(12);
+        for cat in ["general", "net", "disk"] {
+            for suffix in ["s", "m", "h", "d"] {
+                let name = format!("{}_{}", cat, suffix);
+                let (rows, oldest) = conn.query_row(
+                    &format!("SELECT COUNT(*), MIN(timestamp) FROM {}", name),
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                // dbstat may not be compiled in, the size is only informative
+                let size = conn
+                    .query_row(
+                        "SELECT SUM(pgsize) FROM dbstat WHERE name IN
+                            (SELECT name FROM sqlite_master WHERE tbl_name = ?)",
+                        params![name],
+                        |row| row.get(0),
+                    )
+                    .ok();
+                tables.push(TableStats {
+                    name,
+                    rows,
+                    oldest,
+                    size,
+                    retention: retention.for_suffix(suffix),
+                });
+            }
+        }
+
+        Ok(DbStats {
+            file_size: page_size * page_count,
+            free_size: page_size * free_pages,
+            tables,
+        })
+    }
+
     pub fn get_resource_list(&self) -> Result<Vec<AlertVar>> {
         let mut alert_vars: Vec<AlertVar> = Vec::new();
 
@@ -329,7 +1109,26 @@ impl Database {
     }
 }
 
-pub async fn db_update(sys: Arc<Mutex<System>>, db_path: &str) {
+/// Delete the rows older than the retention of their table
+fn cleanup_old_data(conn: &Connection, retention: &Retention, now: u64) {
+    for suffix in ["s", "m", "h", "d"] {
+        let keep = retention.for_suffix(suffix);
+        if keep == 0 {
+            continue;
+        }
+        let cutoff = now.saturating_sub(keep);
+        for cat in ["general", "net", "disk"] {
+            if let Err(e) = conn.execute(
+                &format!("DELETE FROM {}_{} WHERE timestamp < ?", cat, suffix),
+                params![cutoff as i64],
+            ) {
+                error!("Failed to clean up {}_{}: {}", cat, suffix, e);
+            }
+        }
+    }
+}
+
+pub async fn db_update(sys: Arc<Mutex<System>>, db_path: &str, retention: Retention) {
     let db = match Database::new(db_path) {
         Ok(db) => Arc::new(db),
         Err(e) => {
@@ -339,6 +1138,8 @@ pub async fn db_update(sys: Arc<Mutex<System>>, db_path: &str) {
     };
     let mut last_info: Option<GeneralInfo> = None;
     let mut last_timestamp: Option<u64> = None;
+    let mut last_cleanup_hour: Option<u64> = None;
+    let mut last_vacuum_day: Option<u64> = None;
     loop {
         let general_info = {
             let sys = sys.lock().unwrap();
@@ -458,261 +1259,28 @@ pub async fn db_update(sys: Arc<Mutex<System>>, db_path: &str) {
                 .unwrap();
             }
 
-            // if skipped over the minute mark still need to aggregate the last minute's data
-
-            if timestamp % 60 < STORE_INTERVAL {
-                // We have passed (or are on) a minute boundary
-                // Set timestamp to the minute boundary
-                let timestamp = timestamp - (timestamp % 60);
-                // Aggregate last minute's data
-                let _ = conn.execute(
-                    "INSERT INTO general_m
-                                    (
-                                    timestamp,
-                                    cpu_usage,
-                                    mem_usage,
-                                    swap_usage,
-                                    load_avg_1,
-                                    load_avg_5,
-                                    load_avg_15
-                                    )
-                                    SELECT 
-                                        ?2,
-                                        round(AVG(cpu_usage), 2),
-                                        round(AVG(mem_usage), 2),
-                                        round(AVG(swap_usage), 2),
-                                        round(AVG(load_avg_1), 2),
-                                        round(AVG(load_avg_5), 2),
-                                        round(AVG(load_avg_15), 2)
-                                    FROM general_s
-                                    WHERE timestamp >= ?1 AND timestamp <= ?2;",
-                    params![(timestamp - 60) as i64, timestamp as i64],
-                );
-                let _ = conn.execute(
-                    "INSERT INTO net_m
-                                    (
-                                    timestamp,
-                                    name,
-                                    rx,
-                                    tx,
-                                    rx_rate,
-                                    tx_rate
-                                    )
-                                    SELECT 
-                                        ?2,
-                                        name,
-                                        MAX(rx),
-                                        MAX(tx),
-                                        round(AVG(rx_rate)),
-                                        round(AVG(tx_rate))
-                                    FROM net_s
-                                    WHERE timestamp >= ?1 AND timestamp <= ?2
-                                    GROUP BY name;",
-                    params![(timestamp - 60) as i64, timestamp as i64],
-                );
-                let _ = conn.execute(
-                    "INSERT INTO disk_m
-                                    (
-                                    timestamp,
-                                    name,
-                                    total_read,
-                                    total_write,
-                                    read_rate,
-                                    write_rate,
-                                    disk_usage
-                                    )
-                                    SELECT 
-                                        ?2,
-                                        name,
-                                        MAX(total_read),
-                                        MAX(total_write),
-                                        round(AVG(read_rate)),
-                                        round(AVG(write_rate)),
-                                        round(AVG(disk_usage), 2)
-                                    FROM disk_s
-                                    WHERE timestamp >= ?1 AND timestamp <= ?2
-                                    GROUP BY name;",
-                    params![(timestamp - 60) as i64, timestamp as i64],
-                );
-
-                // Check if it's an hour boundary
-                if (timestamp / 60).is_multiple_of(60) {
-                    // Aggregate minute_metrics for the last hour
-                    let _ = conn.execute(
-                        "INSERT INTO general_h
-                                        (
-                                        timestamp,
-                                        cpu_usage,
-                                        mem_usage,
-                                        swap_usage,
-                                        load_avg_1,
-                                        load_avg_5,
-                                        load_avg_15
-                                        )
-                                        SELECT 
-                                            ?2,
-                                            round(AVG(cpu_usage)),
-                                            round(AVG(mem_usage)),
-                                            round(AVG(swap_usage)),
-                                            round(AVG(load_avg_1)),
-                                            round(AVG(load_avg_5)),
-                                            round(AVG(load_avg_15))
-                                        FROM general_m
-                                        WHERE timestamp >= ?1 AND timestamp <= ?2;",
-                        params![(timestamp - 3600) as i64, timestamp as i64],
-                    );
-                    let _ = conn.execute(
-                        "INSERT INTO net_h
-                                        (
-                                        timestamp,
-                                        name,
-                                        rx,
-                                        tx,
-                                        rx_rate,
-                                        tx_rate
-                                        )
-                                        SELECT 
-                                            ?2,
-                                            name,
-                                            MAX(rx),
-                                            MAX(tx),
-                                            round(AVG(rx_rate)),
-                                            round(AVG(tx_rate))
-                                        FROM net_m
-                                        WHERE timestamp >= ?1 AND timestamp <= ?2
-                                        GROUP BY name;",
-                        params![(timestamp - 3600) as i64, timestamp as i64],
-                    );
-                    let _ = conn.execute(
-                        "INSERT INTO disk_h
-                                        (
-                                        timestamp,
-                                        name,
-                                        total_read,
-                                        total_write,
-                                        read_rate,
-                                        write_rate,
-                                        disk_usage
-                                        )
-                                        SELECT 
-                                            ?2,
-                                            name,
-                                            MAX(total_read),
-                                            MAX(total_write),
-                                            round(AVG(read_rate)),
-                                            round(AVG(write_rate)),
-                                            round(AVG(disk_usage), 2)
-                                        FROM disk_m
-                                        WHERE timestamp >= ?1 AND timestamp <= ?2
-                                        GROUP BY name;",
-                        params![(timestamp - 3600) as i64, timestamp as i64],
-                    );
-                    // Check if it's a day boundary (midnight)
-                    if (timestamp / 3600).is_multiple_of(24) {
-                        // Aggregate hour_metrics for the last day
-                        let _ = conn.execute(
-                            "INSERT INTO general_d
-                                            (
-                                            timestamp,
-                                            cpu_usage,
-                                            mem_usage,
-                                            swap_usage,
-                                            load_avg_1,
-                                            load_avg_5,
-                                            load_avg_15
-                                            )
-                                            SELECT 
-                                                ?2,
-                                                round(AVG(cpu_usage)),
-                                                round(AVG(mem_usage)),
-                                                round(AVG(swap_usage)),
-                                                round(AVG(load_avg_1)),
-                                                round(AVG(load_avg_5)),
-                                                round(AVG(load_avg_15))
-                                            FROM general_h
-                                            WHERE timestamp >= ?1 AND timestamp <= ?2;",
-                            params![(timestamp - 86400) as i64, timestamp as i64],
-                        );
-                        let _ = conn.execute(
-                            "INSERT INTO net_d
-                                            (
-                                            timestamp,
-                                            name,
-                                            rx,
-                                            tx,
-                                            rx_rate,
-                                            tx_rate
-                                            )
-                                            SELECT 
-                                                ?2,
-                                                name,
-                                                MAX(rx),
-                                                MAX(tx),
-                                                round(AVG(rx_rate)),
-                                                round(AVG(tx_rate))
-                                            FROM net_h
-                                            WHERE timestamp >= ?1 AND timestamp <= ?2
-                                            GROUP BY name;",
-                            params![(timestamp - 86400) as i64, timestamp as i64],
-                        );
-                        let _ = conn.execute(
-                            "INSERT INTO disk_d
-                                            (
-                                            timestamp,
-                                            name,
-                                            total_read,
-                                            total_write,
-                                            read_rate,
-                                            write_rate,
-                                            disk_usage
-                                            )
-                                            SELECT 
-                                                ?2,
-                                                name,
-                                                MAX(total_read),
-                                                MAX(total_write),
-                                                round(AVG(read_rate)),
-                                                round(AVG(write_rate)),
-                                                round(AVG(disk_usage), 2)
-                                            FROM disk_h
-                                            WHERE timestamp >= ?1 AND timestamp <= ?2
-                                            GROUP BY name;",
-                            params![(timestamp - 86400) as i64, timestamp as i64],
-                        );
-
-                        // Clean up older hour metrics (keep 365 days)
-                        let cutoff = timestamp - (86400 * 365);
-                        for table_name in ["general_h", "net_h", "disk_h"] {
-                            conn.execute(
-                                format!("DELETE FROM {} WHERE timestamp < ?", table_name).as_str(),
-                                params![cutoff as i64],
-                            )
-                            .unwrap();
-                        }
-                        // Run VACCUM
-                        conn.execute("VACUUM", []).unwrap();
-                        conn.execute("pragma optimize", []).unwrap();
-                    }
-                    // Clean up older second data (keep 1 hours)
-                    let cutoff = timestamp - 3600;
-                    for table_name in ["general_s", "net_s", "disk_s"] {
-                        conn.execute(
-                            format!("DELETE FROM {} WHERE timestamp < ?", table_name).as_str(),
-                            params![cutoff as i64],
-                        )
-                        .unwrap();
-                    }
-                    // Clean up older minute metrics (keep 96 hours)
-                    let cutoff = timestamp - (86400 * 4);
-                    for table_name in ["general_m", "net_m", "disk_m"] {
-                        conn.execute(
-                            format!("DELETE FROM {} WHERE timestamp < ?", table_name).as_str(),
-                            params![cutoff as i64],
-                        )
-                        .unwrap();
-                    }
+            // Aggregate whatever complete buckets are due, including the ones missed while stopped
+            if let Err(e) = run_rollups(&conn, timestamp as i64) {
+                error!("Failed to roll up metrics: {}", e);
+            }
+
+            // Clean up data past its retention once an hour
+            let hour = timestamp / 3600;
+            if last_cleanup_hour != Some(hour) {
+                cleanup_old_data(&conn, &retention, timestamp);
+                last_cleanup_hour = Some(hour);
+            }
+
+            // Run VACUUM when the day changes
+            let day = timestamp / 86400;
+            if last_vacuum_day.is_some_and(|last_day| last_day != day) {
+                if let Err(e) = conn.execute("VACUUM", []) {
+                    error!("Failed to vacuum database: {}", e);
                 }
+                let _ = conn.execute("pragma optimize", []);
             }
+            last_vacuum_day = Some(day);
+
             last_info = Some(general_info.clone());
             last_timestamp = Some(timestamp);
         }
@@ -720,3 +1288,136 @@ pub async fn db_update(sys: Arc<Mutex<System>>, db_path: &str) {
         tokio::time::sleep(Duration::from_secs(STORE_INTERVAL)).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minute_rollup_statistics() {
+        let db = Database::new(":memory:").unwrap();
+        let conn = db.conn.lock().unwrap();
+        for i in 1..=100 {
+            conn.execute(
+                "INSERT INTO general_s (timestamp, cpu_usage, mem_usage, swap_usage, load_avg_1, load_avg_5, load_avg_15)
+                 VALUES (?, ?, 50, 0, 1, 1, 1)",
+                params![1000 + i, i as f64],
+            )
+            .unwrap();
+        }
+        conn.execute(&rollup_query("general", "s", "m"), params![1001, 1101])
+            .unwrap();
+
+        let row: (f64, f64, f64, f64, f64) = conn
+            .query_row(
+                "SELECT cpu_usage, cpu_usage_min, cpu_usage_max, cpu_usage_p95, mem_usage_max FROM general_m",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+            )
+            .unwrap();
+        assert_eq!(row, (50.5, 1.0, 100.0, 95.0, 50.0));
+    }
+
+    #[test]
+    fn test_rollups_backfill_missed_buckets() {
+        let db = Database::new(":memory:").unwrap();
+        let conn = db.conn.lock().unwrap();
+        // A few minutes of data, then a gap as if simon was stopped for an hour
+        let timestamps = (3590..=3730).step_by(10).chain([7300]);
+        for t in timestamps {
+            conn.execute(
+                "INSERT INTO general_s (timestamp, cpu_usage, mem_usage, swap_usage, load_avg_1, load_avg_5, load_avg_15)
+                 VALUES (?, 10, 50, 0, 1, 1, 1)",
+                params![t],
+            )
+            .unwrap();
+        }
+        let buckets = |table: &str| -> Vec<i64> {
+            conn.prepare(&format!(
+                "SELECT timestamp FROM {} ORDER BY timestamp",
+                table
+            ))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+        };
+
+        run_rollups(&conn, 7400).unwrap();
+        assert_eq!(buckets("general_m"), vec![3600, 3660, 3720, 3780, 7320]);
+        assert_eq!(buckets("general_h"), vec![7200]);
+        assert!(buckets("general_d").is_empty());
+
+        // Running again, or after the clock jumped backwards, doesn't duplicate anything
+        run_rollups(&conn, 7400).unwrap();
+        run_rollups(&conn, 100).unwrap();
+        assert_eq!(buckets("general_m"), vec![3600, 3660, 3720, 3780, 7320]);
+        assert_eq!(buckets("general_h"), vec![7200]);
+    }
+
+    #[test]
+    fn test_rollups_after_clock_moved_backwards() {
+        let db = Database::new(":memory:").unwrap();
+        let conn = db.conn.lock().unwrap();
+        let insert = |t: i64, cpu: f64| {
+            conn.execute(
+                "INSERT INTO general_s (timestamp, cpu_usage, mem_usage, swap_usage, load_avg_1, load_avg_5, load_avg_15)
+                 VALUES (?, ?, 50, 0, 1, 1, 1)",
+                params![t, cpu],
+            )
+            .unwrap();
+        };
+        let minutes = || -> Vec<(i64, f64)> {
+            conn.prepare("SELECT timestamp, cpu_usage FROM general_m ORDER BY timestamp")
+                .unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap()
+        };
+
+        insert(19000, 10.0);
+        run_rollups(&conn, 20000).unwrap();
+        assert_eq!(minutes(), vec![(19020, 10.0)]);
+
+        // Three hours back, rollups carry on from there instead of waiting for the old time
+        let now = 20000 - 3 * 3600;
+        run_rollups(&conn, now).unwrap();
+        // Rows on a bucket boundary belong to the bucket starting there
+        for (t, cpu) in [(9240, 20.0), (9250, 20.0), (9300, 30.0)] {
+            insert(t, cpu);
+        }
+        run_rollups(&conn, now + 200).unwrap();
+        assert_eq!(minutes(), vec![(9300, 20.0), (9360, 30.0), (19020, 10.0)]);
+    }
+
+    #[test]
+    fn test_due_notifications_drop_unreadable() {
+        let db = Database::new(":memory:").unwrap();
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute_batch(
+                "INSERT INTO notification_methods (id, name, kind, enabled, config)
+                 VALUES ('m', 'hook', 'webhook', 1, '{}');
+                 INSERT INTO notification_queue (method_id, context, next_attempt)
+                 VALUES ('m', '{\"state\": \"firing\"', 0), ('m', '{\"state\": \"firing\"}', 0);",
+            )
+            .unwrap();
+        }
+
+        let due = db.due_notifications(0).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].context["state"], "firing");
+        // Dropped from the queue, not picked up again
+        let queued: i64 = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM notification_queue", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(queued, 1);
+    }
+}
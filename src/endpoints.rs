@@ -1,15 +1,19 @@
+use crate::alerts;
 use crate::collect_info;
 use crate::config::Config;
 use crate::db::Database;
+use crate::hub::{self, Hub};
+use crate::metrics;
 use crate::models::{
     self, ApiResponse, CreateFolderPayload, DirectoryListing, FileEntry, FilePathPayload,
     MoveFilePayload, NotificationMethod,
 };
+use crate::notifications;
+use crate::silences;
 use axum::Json;
 use axum::body::Body;
 use axum::extract::rejection::JsonRejection;
-use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{Multipart, Path, Query, Request};
+use axum::extract::{Extension, Multipart, Path, Query, Request};
 use axum::http::{HeaderValue, StatusCode};
 use axum::{
     extract::{ConnectInfo, State, WebSocketUpgrade},
@@ -26,11 +30,7 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use sysinfo::System;
-use tokio::{
-    self,
-    io::AsyncWriteExt,
-    time::{Duration, interval},
-};
+use tokio::{self, io::AsyncWriteExt, time::Duration};
 use tower::ServiceExt;
 use tower_http::services::ServeFile;
 
@@ -223,111 +223,62 @@ pub async fn capabilities_handler(
     Json(ApiResponse::success(config.system_capabilities.clone())).into_response()
 }
 
+// Prometheus metrics
+pub async fn metrics_handler(
+    State((sys, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+) -> impl IntoResponse {
+    debug!("Metrics requested");
+    let general_info = collect_info::collect_general_info(&sys.lock().unwrap());
+    let docker_info = if config.system_capabilities.docker {
+        collect_info::get_docker_containers().await
+    } else {
+        None
+    };
+
+    (
+        [(axum::http::header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        metrics::render(&general_info, docker_info.as_ref()),
+    )
+}
+
 // docker
 pub async fn ws_handler_d(
     ws: WebSocketUpgrade,
-    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    Extension(hub): Extension<Arc<Hub>>,
 ) -> impl IntoResponse {
     debug!("Docker websocket connection requested");
-    ws.on_upgrade(move |socket| handle_socket_d(socket, config.update_interval))
-}
-
-async fn handle_socket_d(mut socket: WebSocket, ws_interval: u64) {
-    debug!("Docker websocket connection established");
-    let mut interval = interval(Duration::from_secs(ws_interval));
-
-    let mut docker_accessible = true;
-    loop {
-        let json_string = match collect_info::get_docker_containers().await {
-            Some(info) => serde_json::to_string(&info).unwrap(),
-            None => {
-                warn!("Can't get docker containers info");
-                docker_accessible = false;
-                String::from("null")
-            }
-        };
-        if socket
-            .send(Message::Binary({
-                let mut encoder =
-                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
-                std::io::Write::write_all(&mut encoder, json_string.as_bytes()).unwrap();
-                encoder.finish().unwrap().into()
-            }))
-            .await
-            .is_err()
-        {
-            debug!("Docker websocket connection closed");
-            break;
-        }
-        if !docker_accessible {
-            break;
-        }
-        interval.tick().await;
-    }
+    ws.on_upgrade(move |socket| async move {
+        debug!("Docker websocket connection established");
+        // Close the connection once docker turns out to be inaccessible
+        hub::forward_topic(socket, &hub.docker, |info| info.is_none()).await;
+        debug!("Docker websocket connection closed");
+    })
 }
 
 // processes
 pub async fn ws_handler_p(
     ws: WebSocketUpgrade,
-    State((sys, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    Extension(hub): Extension<Arc<Hub>>,
 ) -> impl IntoResponse {
     debug!("Processes websocket connection requested");
-    ws.on_upgrade(move |socket| handle_socket_p(socket, sys, config.update_interval))
-}
-
-async fn handle_socket_p(mut socket: WebSocket, sys: Arc<Mutex<System>>, ws_interval: u64) {
-    debug!("Processes websocket connection established");
-    let mut interval = interval(Duration::from_secs(ws_interval));
-    loop {
-        let processes_info = collect_info::collect_processes_info(&sys.lock().unwrap());
-        if socket
-            .send(Message::Binary({
-                let json_string = serde_json::to_string(&processes_info).unwrap();
-                let mut encoder =
-                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
-                std::io::Write::write_all(&mut encoder, json_string.as_bytes()).unwrap();
-                encoder.finish().unwrap().into()
-            }))
-            .await
-            .is_err()
-        {
-            debug!("Processes websocket connection closed");
-            break;
-        }
-        interval.tick().await;
-    }
+    ws.on_upgrade(move |socket| async move {
+        debug!("Processes websocket connection established");
+        hub::forward_topic(socket, &hub.processes, |_| false).await;
+        debug!("Processes websocket connection closed");
+    })
 }
 
 // general info
 pub async fn ws_handler_g(
     ws: WebSocketUpgrade,
-    State((sys, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    Extension(hub): Extension<Arc<Hub>>,
 ) -> impl IntoResponse {
     debug!("General system info websocket connection requested");
-    ws.on_upgrade(move |socket| handle_socket_g(socket, sys, config.update_interval))
-}
-
-async fn handle_socket_g(mut socket: WebSocket, sys: Arc<Mutex<System>>, ws_interval: u64) {
-    debug!("General system info websocket connection established");
-    let mut interval = interval(Duration::from_secs(ws_interval));
-    loop {
-        let general_info = collect_info::collect_general_info(&sys.lock().unwrap());
-        if socket
-            .send(Message::Binary({
-                let json_string = serde_json::to_string(&general_info).unwrap();
-                let mut encoder =
-                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
-                std::io::Write::write_all(&mut encoder, json_string.as_bytes()).unwrap();
-                encoder.finish().unwrap().into()
-            }))
-            .await
-            .is_err()
-        {
-            debug!("General system info websocket connection closed");
-            break;
-        }
-        interval.tick().await;
-    }
+    ws.on_upgrade(move |socket| async move {
+        debug!("General system info websocket connection established");
+        hub::forward_topic(socket, &hub.general, |_| false).await;
+        debug!("General system info websocket connection closed");
+    })
 }
 
 pub async fn get_container_logs(Path(container_id): Path<String>) -> impl IntoResponse {
@@ -403,6 +354,33 @@ pub async fn historical_data(
     }
 }
 
+pub async fn db_stats(
+    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+) -> impl IntoResponse {
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            return Json(ApiResponse::<models::DbStats>::error(format!(
+                "Failed to open database: {}",
+                e
+            )))
+            .into_response();
+        }
+    };
+
+    match db.get_db_stats(&config.retention()) {
+        Ok(stats) => Json(ApiResponse::success(stats)).into_response(),
+        Err(e) => {
+            error!("Failed to get database stats: {}", e);
+            Json(ApiResponse::<models::DbStats>::error(format!(
+                "Failed to get database stats: {}",
+                e
+            )))
+            .into_response()
+        }
+    }
+}
+
 pub async fn add_notif_method(
     State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
     body: Result<Json<NotificationMethod>, JsonRejection>,
@@ -422,6 +400,15 @@ pub async fn add_notif_method(
         }
     };
 
+    notification_method.template = notification_method
+        .template
+        .filter(|t| !t.trim().is_empty());
+
+    if let Err(e) = notifications::validate_method(&notification_method) {
+        warn!("Rejected invalid notification method: {}", e);
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))).into_response();
+    }
+
     info!("Adding notification method: {}", notification_method.name);
     debug!("Notification method details: {:?}", notification_method);
 
@@ -440,12 +427,6 @@ pub async fn add_notif_method(
         }
     };
 
-    let mut methods: Vec<NotificationMethod> =
-        match db.get_kv_str("notification_methods").unwrap_or_default() {
-            Some(methods) => serde_json::from_str(&methods).unwrap(),
-            None => Vec::new(),
-        };
-
     if notification_method.id == "-1" {
         notification_method.id = uuid::Uuid::new_v4().to_string();
         info!(
@@ -457,18 +438,28 @@ pub async fn add_notif_method(
             "Updating notification method with ID: {}",
             notification_method.id
         );
-        methods.retain(|method| method.id != notification_method.id);
     }
 
-    methods.push(notification_method);
-
-    db.set_kv_str(
-        "notification_methods",
-        &serde_json::to_string(&methods).unwrap().to_string(),
-    )
-    .unwrap();
+    if let Err(e) = db.save_notification_method(&notification_method) {
+        error!("Failed to save notification method: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(format!(
+                "Failed to save notification method: {}",
+                e
+            ))),
+        )
+            .into_response();
+    }
 
-    (StatusCode::CREATED, Json(ApiResponse::success(methods))).into_response()
+    match db.get_notification_methods() {
+        Ok(methods) => (StatusCode::CREATED, Json(ApiResponse::success(methods))).into_response(),
+        Err(e) => Json(ApiResponse::<()>::error(format!(
+            "Failed to get notification methods: {}",
+            e
+        )))
+        .into_response(),
+    }
 }
 
 pub async fn get_notif_methods(
@@ -485,13 +476,94 @@ pub async fn get_notif_methods(
         }
     };
 
-    let methods: Vec<NotificationMethod> =
-        match db.get_kv_str("notification_methods").unwrap_or_default() {
-            Some(methods) => serde_json::from_str(&methods).unwrap(),
-            None => Vec::new(),
-        };
+    match db.get_notification_methods() {
+        Ok(methods) => Json(ApiResponse::success(methods)).into_response(),
+        Err(e) => Json(ApiResponse::<Vec<NotificationMethod>>::error(format!(
+            "Failed to get notification methods: {}",
+            e
+        )))
+        .into_response(),
+    }
+}
+
+/// Send a sample notification through a saved method
+pub async fn test_notif_method(
+    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Failed to open database: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(format!(
+                    "Failed to open database: {}",
+                    e
+                ))),
+            )
+                .into_response();
+        }
+    };
 
-    Json(ApiResponse::success(methods)).into_response()
+    let method = match db.get_notification_methods() {
+        Ok(methods) => methods.into_iter().find(|m| m.id == id),
+        Err(e) => {
+            error!("Failed to get notification methods: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(format!(
+                    "Failed to get notification methods: {}",
+                    e
+                ))),
+            )
+                .into_response();
+        }
+    };
+    let Some(method) = method else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!(
+                "Notification method {} not found",
+                id
+            ))),
+        )
+            .into_response();
+    };
+
+    info!("Sending test notification through {}", method.name);
+    let result = notifications::send_test_notification(&method).await;
+    Json(ApiResponse::success(result)).into_response()
+}
+
+/// Send a sample notification through a method that isn't saved yet, e.g. while editing it
+pub async fn test_unsaved_notif_method(
+    body: Result<Json<NotificationMethod>, JsonRejection>,
+) -> impl IntoResponse {
+    let mut method = match body {
+        Ok(Json(method)) => method,
+        Err(err) => {
+            error!("Invalid notification method JSON payload: {}", err);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(format!(
+                    "Invalid JSON payload: {}",
+                    err
+                ))),
+            )
+                .into_response();
+        }
+    };
+
+    method.template = method.template.filter(|t| !t.trim().is_empty());
+    if let Err(e) = notifications::validate_method(&method) {
+        warn!("Rejected invalid notification method: {}", e);
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))).into_response();
+    }
+
+    info!("Sending test notification through unsaved {}", method.name);
+    let result = notifications::send_test_notification(&method).await;
+    Json(ApiResponse::success(result)).into_response()
 }
 
 pub async fn delete_notif_method(
@@ -509,25 +581,28 @@ pub async fn delete_notif_method(
         }
     };
 
-    let mut methods: Vec<NotificationMethod> =
-        match db.get_kv_str("notification_methods").unwrap_or_default() {
-            Some(methods) => serde_json::from_str(&methods).unwrap(),
-            None => Vec::new(),
-        };
-
-    methods.retain(|source| source.id != id);
-
-    db.set_kv_str(
-        "notification_methods",
-        &serde_json::to_string(&methods).unwrap().to_string(),
-    )
-    .unwrap();
+    if let Err(e) = db.delete_notification_method(&id) {
+        error!("Failed to delete notification method: {}", e);
+        return Json(ApiResponse::<Vec<NotificationMethod>>::error(format!(
+            "Failed to delete notification method: {}",
+            e
+        )))
+        .into_response();
+    }
 
-    Json(ApiResponse::success(methods)).into_response()
+    match db.get_notification_methods() {
+        Ok(methods) => Json(ApiResponse::success(methods)).into_response(),
+        Err(e) => Json(ApiResponse::<Vec<NotificationMethod>>::error(format!(
+            "Failed to get notification methods: {}",
+            e
+        )))
+        .into_response(),
+    }
 }
 
 pub async fn add_alert(
     State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    Extension(hub): Extension<Arc<Hub>>,
     body: Result<Json<models::Alert>, JsonRejection>,
 ) -> impl IntoResponse {
     let mut alert = match body {
@@ -544,7 +619,13 @@ pub async fn add_alert(
                 .into_response();
         }
     };
-    alert.firing = false;
+    // Forms send empty templates for the default message
+    alert.template = alert.template.filter(|t| !t.trim().is_empty());
+
+    if let Err(e) = alerts::validate_alert(&alert) {
+        warn!("Rejected invalid alert: {}", e);
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))).into_response();
+    }
 
     info!("Adding alert for {}", alert.var.var);
     debug!("Alert details: {:?}", alert);
@@ -564,28 +645,46 @@ pub async fn add_alert(
         }
     };
 
-    let mut alerts: Vec<models::Alert> = match db.get_kv_str("alerts").unwrap_or_default() {
-        Some(alerts) => serde_json::from_str(&alerts).unwrap(),
-        None => Vec::new(),
-    };
-
     if alert.id == "-1" {
         alert.id = uuid::Uuid::new_v4().to_string();
         info!("Created new alert with ID: {}", alert.id);
     } else {
         info!("Updating alert with ID: {}", alert.id);
-        alerts.retain(|a| a.id != alert.id);
+        // A firing alert isn't left firing on a condition it no longer has
+        let stored = db
+            .get_alerts()
+            .map(|alerts| alerts.into_iter().find(|a| a.id == alert.id));
+        match stored {
+            Ok(Some(stored)) if stored.firing && alerts::condition_changed(&stored, &alert) => {
+                if let Err(e) = alerts::resolve_edited_alert(&db, &hub, stored) {
+                    error!("Failed to resolve edited alert: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to get alerts: {}", e),
+        }
     }
 
-    alerts.push(alert);
-
-    db.set_kv_str(
-        "alerts",
-        &serde_json::to_string(&alerts).unwrap().to_string(),
-    )
-    .unwrap();
+    if let Err(e) = db.save_alert(&alert) {
+        error!("Failed to save alert: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(format!(
+                "Failed to save alert: {}",
+                e
+            ))),
+        )
+            .into_response();
+    }
 
-    (StatusCode::CREATED, Json(ApiResponse::success(alerts))).into_response()
+    match db.get_alerts() {
+        Ok(alerts) => (StatusCode::CREATED, Json(ApiResponse::success(alerts))).into_response(),
+        Err(e) => Json(ApiResponse::<()>::error(format!(
+            "Failed to get alerts: {}",
+            e
+        )))
+        .into_response(),
+    }
 }
 
 pub async fn get_alerts(
@@ -602,12 +701,70 @@ pub async fn get_alerts(
         }
     };
 
-    let alerts: Vec<models::Alert> = match db.get_kv_str("alerts").unwrap_or_default() {
-        Some(alerts) => serde_json::from_str(&alerts).unwrap(),
-        None => Vec::new(),
+    match db.get_alerts() {
+        Ok(alerts) => Json(ApiResponse::success(alerts)).into_response(),
+        Err(e) => Json(ApiResponse::<Vec<models::Alert>>::error(format!(
+            "Failed to get alerts: {}",
+            e
+        )))
+        .into_response(),
+    }
+}
+
+pub async fn get_alert_history(
+    Query(query): Query<models::AlertHistoryQuery>,
+    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+) -> impl IntoResponse {
+    debug!("Alert history requested: {:?}", query);
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            return Json(ApiResponse::<Vec<models::AlertEvent>>::error(format!(
+                "Failed to open database: {}",
+                e
+            )))
+            .into_response();
+        }
     };
 
-    Json(ApiResponse::success(alerts)).into_response()
+    match db.query_alert_events(&query) {
+        Ok(events) => Json(ApiResponse::success(events)).into_response(),
+        Err(e) => {
+            error!("Failed to query alert history: {}", e);
+            Json(ApiResponse::<Vec<models::AlertEvent>>::error(format!(
+                "Failed to query alert history: {}",
+                e
+            )))
+            .into_response()
+        }
+    }
+}
+
+pub async fn get_notif_deliveries(
+    Query(query): Query<models::DeliveryLogQuery>,
+    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+) -> impl IntoResponse {
+    debug!("Notification delivery log requested: {:?}", query);
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            return Json(ApiResponse::<Vec<models::DeliveryLogEntry>>::error(
+                format!("Failed to open database: {}", e),
+            ))
+            .into_response();
+        }
+    };
+
+    match db.query_deliveries(&query) {
+        Ok(deliveries) => Json(ApiResponse::success(deliveries)).into_response(),
+        Err(e) => {
+            error!("Failed to query notification deliveries: {}", e);
+            Json(ApiResponse::<Vec<models::DeliveryLogEntry>>::error(
+                format!("Failed to query notification deliveries: {}", e),
+            ))
+            .into_response()
+        }
+    }
 }
 
 pub async fn delete_alert(
@@ -628,24 +785,157 @@ pub async fn delete_alert(
         }
     };
 
-    let mut alerts: Vec<models::Alert> = match db.get_kv_str("alerts").unwrap_or_default() {
-        Some(alerts) => serde_json::from_str(&alerts).unwrap(),
-        None => Vec::new(),
+    if let Err(e) = db.delete_alert(&id) {
+        error!("Failed to delete alert: {}", e);
+        return Json(ApiResponse::<Vec<models::Alert>>::error(format!(
+            "Failed to delete alert: {}",
+            e
+        )))
+        .into_response();
+    }
+
+    match db.get_alerts() {
+        Ok(alerts) => Json(ApiResponse::success(alerts)).into_response(),
+        Err(e) => Json(ApiResponse::<Vec<models::Alert>>::error(format!(
+            "Failed to get alerts: {}",
+            e
+        )))
+        .into_response(),
+    }
+}
+
+pub async fn get_silences(
+    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+) -> impl IntoResponse {
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            return Json(ApiResponse::<Vec<models::Silence>>::error(format!(
+                "Failed to open database: {}",
+                e
+            )))
+            .into_response();
+        }
     };
 
-    alerts.retain(|alert| alert.id != id);
+    match db.get_silences() {
+        Ok(silences) => Json(ApiResponse::success(silences)).into_response(),
+        Err(e) => Json(ApiResponse::<Vec<models::Silence>>::error(format!(
+            "Failed to get silences: {}",
+            e
+        )))
+        .into_response(),
+    }
+}
 
-    db.set_kv_str(
-        "alerts",
-        &serde_json::to_string(&alerts).unwrap().to_string(),
-    )
-    .unwrap();
+pub async fn add_silence(
+    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    body: Result<Json<models::Silence>, JsonRejection>,
+) -> impl IntoResponse {
+    let mut silence = match body {
+        Ok(Json(silence)) => silence,
+        Err(err) => {
+            error!("Invalid silence JSON payload: {}", err);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(format!(
+                    "Invalid JSON payload: {}",
+                    err
+                ))),
+            )
+                .into_response();
+        }
+    };
+
+    if let Err(e) = silences::validate_silence(&silence) {
+        warn!("Rejected invalid silence: {}", e);
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))).into_response();
+    }
+
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Failed to open database: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(format!(
+                    "Failed to open database: {}",
+                    e
+                ))),
+            )
+                .into_response();
+        }
+    };
+
+    if silence.id == "-1" {
+        silence.id = uuid::Uuid::new_v4().to_string();
+        info!("Created new silence with ID: {}", silence.id);
+    } else {
+        info!("Updating silence with ID: {}", silence.id);
+    }
+    debug!("Silence details: {:?}", silence);
+
+    if let Err(e) = db.save_silence(&silence) {
+        error!("Failed to save silence: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(format!(
+                "Failed to save silence: {}",
+                e
+            ))),
+        )
+            .into_response();
+    }
 
-    Json(ApiResponse::success(alerts)).into_response()
+    match db.get_silences() {
+        Ok(silences) => (StatusCode::CREATED, Json(ApiResponse::success(silences))).into_response(),
+        Err(e) => Json(ApiResponse::<()>::error(format!(
+            "Failed to get silences: {}",
+            e
+        )))
+        .into_response(),
+    }
 }
 
-pub async fn get_alert_vars(
+pub async fn delete_silence(
     State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    info!("Deleting silence with ID: {}", id);
+
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Failed to open database: {}", e);
+            return Json(ApiResponse::<Vec<models::Silence>>::error(format!(
+                "Failed to open database: {}",
+                e
+            )))
+            .into_response();
+        }
+    };
+
+    if let Err(e) = db.delete_silence(&id) {
+        error!("Failed to delete silence: {}", e);
+        return Json(ApiResponse::<Vec<models::Silence>>::error(format!(
+            "Failed to delete silence: {}",
+            e
+        )))
+        .into_response();
+    }
+
+    match db.get_silences() {
+        Ok(silences) => Json(ApiResponse::success(silences)).into_response(),
+        Err(e) => Json(ApiResponse::<Vec<models::Silence>>::error(format!(
+            "Failed to get silences: {}",
+            e
+        )))
+        .into_response(),
+    }
+}
+
+pub async fn get_alert_vars(
+    State((sys, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
 ) -> impl IntoResponse {
     let db = match Database::new(&config.db_path) {
         Ok(db) => db,
@@ -658,7 +948,7 @@ pub async fn get_alert_vars(
         }
     };
 
-    let vars: Vec<models::AlertVar> = match db.get_resource_list() {
+    let mut vars: Vec<models::AlertVar> = match db.get_resource_list() {
         Ok(vars) => vars,
         Err(e) => {
             return Json(ApiResponse::<Vec<models::AlertVar>>::error(format!(
@@ -669,6 +959,39 @@ pub async fn get_alert_vars(
         }
     };
 
+    // Processes and containers aren't in the database, list the ones currently running
+    let process_names: std::collections::BTreeSet<String> = {
+        let mut sys = sys.lock().unwrap();
+        sys.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+        sys.processes()
+            .values()
+            .map(|process| process.name().to_string_lossy().into_owned())
+            .collect()
+    };
+    for name in process_names {
+        for &(_, var) in models::ALERT_VARIABLES.iter().filter(|v| v.0 == "proc") {
+            vars.push(models::AlertVar {
+                cat: "proc".to_string(),
+                var: var.to_string(),
+                resrc: name.clone(),
+            });
+        }
+    }
+
+    if config.system_capabilities.docker
+        && let Some(info) = collect_info::get_docker_containers().await
+    {
+        for container in &info.containers {
+            for &(_, var) in models::ALERT_VARIABLES.iter().filter(|v| v.0 == "docker") {
+                vars.push(models::AlertVar {
+                    cat: "docker".to_string(),
+                    var: var.to_string(),
+                    resrc: alerts::container_name(container).to_string(),
+                });
+            }
+        }
+    }
+
     Json(ApiResponse::success(vars)).into_response()
 }
 
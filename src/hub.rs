@@ -0,0 +1,223 @@
+use crate::collect_info;
+use crate::models::{AlertStateChange, DockerInfo, GeneralInfo, ProcessesInfo};
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+use tokio::sync::{Notify, broadcast, watch};
+use tokio::time::{Duration, MissedTickBehavior, interval};
+
+/// A collected value together with its serialized and gzipped form,
+/// so the work is done once no matter how many clients are connected
+pub struct Snapshot<T> {
+    pub data: T,
+    pub frame: Bytes,
+}
+
+impl<T: Serialize> Snapshot<T> {
+    fn new(data: T) -> Self {
+        let json_string = serde_json::to_string(&data).unwrap();
+        let frame = gzip(json_string.as_bytes()).into();
+        Snapshot { data, frame }
+    }
+}
+
+/// Gzip a payload the way the websocket clients expect it
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, data).unwrap();
+    encoder.finish().unwrap()
+}
+
+pub type SnapshotReceiver<T> = watch::Receiver<Option<Arc<Snapshot<T>>>>;
+
+/// Latest snapshot of a single kind of data, fed by one background collector
+pub struct Topic<T> {
+    tx: watch::Sender<Option<Arc<Snapshot<T>>>>,
+    wake: Notify,
+}
+
+impl<T: Serialize> Topic<T> {
+    fn new() -> Self {
+        Topic {
+            tx: watch::Sender::new(None),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Subscribe to the topic, waking up its collector if it was idle
+    pub fn subscribe(&self) -> SnapshotReceiver<T> {
+        let rx = self.tx.subscribe();
+        self.wake.notify_one();
+        rx
+    }
+
+    /// Block until at least one client is subscribed
+    async fn wait_for_subscribers(&self) {
+        if self.tx.receiver_count() > 0 {
+            return;
+        }
+
+        // Drop the stale snapshot so the next subscriber doesn't get outdated data
+        self.tx.send_replace(None);
+        debug!("No subscribers left, collector idling");
+        while self.tx.receiver_count() == 0 {
+            self.wake.notified().await;
+        }
+        debug!("Subscriber connected, collector resuming");
+    }
+
+    fn publish(&self, data: T) {
+        self.tx.send_replace(Some(Arc::new(Snapshot::new(data))));
+    }
+}
+
+/// Shared collectors for the websocket endpoints
+pub struct Hub {
+    pub general: Topic<GeneralInfo>,
+    pub processes: Topic<ProcessesInfo>,
+    pub docker: Topic<Option<DockerInfo>>,
+    pub alerts: broadcast::Sender<AlertStateChange>,
+    pub notifications: Notify, // Wakes the delivery queue when notifications are queued
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Hub {
+            general: Topic::new(),
+            processes: Topic::new(),
+            docker: Topic::new(),
+            alerts: broadcast::Sender::new(64),
+            notifications: Notify::new(),
+        }
+    }
+
+    /// Announce an alert state change to the connected clients, if any
+    pub fn publish_alert_change(&self, change: AlertStateChange) {
+        let _ = self.alerts.send(change);
+    }
+}
+
+/// Spawn the background collectors of all topics, each restarted on panic
+pub fn spawn_collectors(hub: Arc<Hub>, sys: Arc<Mutex<System>>, update_interval: u64) {
+    let (h, s) = (hub.clone(), sys.clone());
+    supervise("General info collector", move || {
+        collect_general(h.clone(), s.clone(), update_interval)
+    });
+    let (h, s) = (hub.clone(), sys);
+    supervise("Processes collector", move || {
+        collect_processes(h.clone(), s.clone(), update_interval)
+    });
+    supervise("Docker collector", move || {
+        collect_docker(hub.clone(), update_interval)
+    });
+}
+
+fn supervise<F, Fut>(name: &'static str, task: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        // Collectors run indefinitely, so they only return when panicking
+        while let Err(e) = tokio::task::spawn(task()).await {
+            error!("{} task panicked: {}", name, e);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            info!("Restarting {} task", name);
+        }
+    });
+    debug!("{} background task started", name);
+}
+
+fn collector_interval(update_interval: u64) -> tokio::time::Interval {
+    let mut interval = interval(Duration::from_secs(update_interval));
+    // Don't burst through the ticks missed while idling
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+async fn collect_general(hub: Arc<Hub>, sys: Arc<Mutex<System>>, update_interval: u64) {
+    let mut interval = collector_interval(update_interval);
+    loop {
+        hub.general.wait_for_subscribers().await;
+        interval.tick().await;
+        let general_info = collect_info::collect_general_info(&sys.lock().unwrap());
+        hub.general.publish(general_info);
+    }
+}
+
+async fn collect_processes(hub: Arc<Hub>, sys: Arc<Mutex<System>>, update_interval: u64) {
+    let mut interval = collector_interval(update_interval);
+    loop {
+        hub.processes.wait_for_subscribers().await;
+        interval.tick().await;
+        let processes_info = {
+            let mut sys = sys.lock().unwrap();
+            // Nothing else keeps the process list of the shared System up to date
+            sys.refresh_processes_specifics(
+                ProcessesToUpdate::All,
+                true,
+                ProcessRefreshKind::nothing()
+                    .with_cpu()
+                    .with_memory()
+                    .with_cmd(UpdateKind::OnlyIfNotSet)
+                    .with_environ(UpdateKind::OnlyIfNotSet),
+            );
+            collect_info::collect_processes_info(&sys)
+        };
+        hub.processes.publish(processes_info);
+    }
+}
+
+async fn collect_docker(hub: Arc<Hub>, update_interval: u64) {
+    let mut interval = collector_interval(update_interval);
+    loop {
+        hub.docker.wait_for_subscribers().await;
+        interval.tick().await;
+        let docker_info = collect_info::get_docker_containers().await;
+        if docker_info.is_none() {
+            warn!("Can't get docker containers info");
+        }
+        hub.docker.publish(docker_info);
+    }
+}
+
+/// Forward the snapshots of a topic to a websocket until either side goes away.
+/// `is_last` decides whether a snapshot should end the connection after being sent.
+pub async fn forward_topic<T: Serialize>(
+    mut socket: WebSocket,
+    topic: &Topic<T>,
+    is_last: impl Fn(&T) -> bool,
+) {
+    let mut rx = topic.subscribe();
+    let mut current = rx.borrow_and_update().clone();
+    loop {
+        if let Some(snapshot) = current.take()
+            && (socket
+                .send(Message::Binary(snapshot.frame.clone()))
+                .await
+                .is_err()
+                || is_last(&snapshot.data))
+        {
+            break;
+        }
+
+        tokio::select! {
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                current = rx.borrow_and_update().clone();
+            }
+            msg = socket.recv() => {
+                // Only used to notice disconnects, clients don't send anything
+                if matches!(msg, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -4,23 +4,33 @@ mod collect_info;
 mod config;
 mod db;
 mod endpoints;
+mod hub;
 mod logging;
+mod metrics;
+mod migrations;
 mod models;
+mod mqtt;
+mod notifications;
+mod silences;
+mod ws;
 
 use alerts::check_alerts;
 use axum::{
-    Router,
+    Extension, Router,
     extract::DefaultBodyLimit,
     routing::{delete, get, post},
 };
 use collect_info::detect_system_capabilities;
-use db::db_update;
+use db::{Database, db_update};
 use endpoints::{
-    add_alert, add_notif_method, browse_directory, create_folder, delete_alert, delete_file,
-    delete_notif_method, download_file, fallback_handler, get_alert_vars, get_alerts,
-    get_container_logs, get_notif_methods, get_serve_dirs, historical_data, move_file, req_info,
-    serve_static, upload_file, ws_handler_d, ws_handler_g, ws_handler_p,
+    add_alert, add_notif_method, add_silence, browse_directory, create_folder, db_stats,
+    delete_alert, delete_file, delete_notif_method, delete_silence, download_file,
+    fallback_handler, get_alert_history, get_alert_vars, get_alerts, get_container_logs,
+    get_notif_deliveries, get_notif_methods, get_serve_dirs, get_silences, historical_data,
+    metrics_handler, move_file, req_info, serve_static, test_notif_method,
+    test_unsaved_notif_method, upload_file, ws_handler_d, ws_handler_g, ws_handler_p,
 };
+use hub::Hub;
 use log::{debug, error, info};
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
@@ -89,11 +99,14 @@ async fn main() {
 
     // Database update background task with restart on panic
     let db_path = config.db_path.clone();
+    let retention = config.retention();
     tokio::spawn(async move {
         loop {
             let db_path = db_path.clone();
             let db_sys = db_sys.clone();
-            let result = tokio::task::spawn(async move { db_update(db_sys, &db_path).await }).await;
+            let result =
+                tokio::task::spawn(async move { db_update(db_sys, &db_path, retention).await })
+                    .await;
             match result {
                 Err(e) => {
                     error!("Database update task panicked: {}", e);
@@ -109,12 +122,32 @@ async fn main() {
     });
     debug!("Database update background task started");
 
+    // Shared collectors feeding the websocket endpoints
+    let hub = Arc::new(Hub::new());
+    hub::spawn_collectors(hub.clone(), shared_sys.clone(), update_interval);
+
+    // Database handle shared by the websocket connections
+    let db = match Database::new(&config.db_path) {
+        Ok(db) => Arc::new(db),
+        Err(e) => {
+            error!("Failed to open database {}: {}", config.db_path, e);
+            std::process::exit(1);
+        }
+    };
+
     let db_path = config.db_path.clone();
+    let alert_interval = config.alert_interval;
+    let alerts_hub = hub.clone();
     // Check alerts background task with restart on panic
     tokio::spawn(async move {
         loop {
             let db_path = db_path.clone();
-            let result = tokio::task::spawn(async move { check_alerts(&db_path).await }).await;
+            let hub = alerts_hub.clone();
+            let result =
+                tokio::task::spawn(
+                    async move { check_alerts(&db_path, hub, alert_interval).await },
+                )
+                .await;
             match result {
                 Err(e) => {
                     error!("Check alerts task panicked: {}", e);
@@ -130,6 +163,57 @@ async fn main() {
     });
     debug!("Alerts checking background task started");
 
+    let db_path = config.db_path.clone();
+    let queue_hub = hub.clone();
+    // Notification delivery background task with restart on panic
+    tokio::spawn(async move {
+        loop {
+            let db_path = db_path.clone();
+            let hub = queue_hub.clone();
+            let result = tokio::task::spawn(async move {
+                notifications::run_delivery_queue(&db_path, hub).await
+            })
+            .await;
+            match result {
+                Err(e) => {
+                    error!("Notification delivery task panicked: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    info!("Restarting notification delivery task");
+                    // Continue the loop to restart the task
+                }
+                _ => {
+                    break; // This should not happen as the delivery queue runs indefinitely
+                }
+            }
+        }
+    });
+    debug!("Notification delivery background task started");
+
+    // MQTT publisher background task with restart on panic
+    if config.mqtt_broker.is_some() {
+        let mqtt_config = shared_config.clone();
+        let mqtt_hub = hub.clone();
+        tokio::spawn(async move {
+            loop {
+                let result =
+                    tokio::task::spawn(mqtt::run_publisher(mqtt_config.clone(), mqtt_hub.clone()))
+                        .await;
+                match result {
+                    Err(e) => {
+                        error!("MQTT publisher task panicked: {}", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        info!("Restarting MQTT publisher task");
+                        // Continue the loop to restart the task
+                    }
+                    _ => {
+                        break; // The publisher only returns once the alert channel is closed
+                    }
+                }
+            }
+        });
+        debug!("MQTT publisher background task started");
+    }
+
     let mut app = Router::new()
         .route("/", get(serve_static))
         .route("/favicon.png", get(serve_static))
@@ -143,15 +227,25 @@ async fn main() {
         .route("/ws/g", get(ws_handler_g))
         .route("/ws/p", get(ws_handler_p))
         .route("/ws/d", get(ws_handler_d))
+        .route("/ws", get(ws::ws_handler))
         .route("/container_logs/{continer_id}", get(get_container_logs))
         .route("/reqinfo", get(req_info))
+        .route("/metrics", get(metrics_handler))
         .route("/api/historical", get(historical_data))
+        .route("/api/db_stats", get(db_stats))
         .route("/api/notif_methods", post(add_notif_method))
         .route("/api/notif_methods", get(get_notif_methods))
+        .route("/api/notif_methods/deliveries", get(get_notif_deliveries))
+        .route("/api/notif_methods/test", post(test_unsaved_notif_method))
+        .route("/api/notif_methods/{id}/test", post(test_notif_method))
         .route("/api/notif_methods/{id}", delete(delete_notif_method))
         .route("/api/alerts", post(add_alert))
         .route("/api/alerts", get(get_alerts))
+        .route("/api/alerts/history", get(get_alert_history))
         .route("/api/alerts/{id}", delete(delete_alert))
+        .route("/api/silences", post(add_silence))
+        .route("/api/silences", get(get_silences))
+        .route("/api/silences/{id}", delete(delete_silence))
         .route("/api/alert_vars", get(get_alert_vars))
         .route("/api/files/dirs", get(get_serve_dirs))
         .route("/api/files/browse", get(browse_directory))
@@ -164,14 +258,21 @@ async fn main() {
         .route("/api/files/move", post(move_file))
         .route("/api/files/delete", delete(delete_file))
         .fallback(fallback_handler)
+        .layer(Extension(hub))
+        .layer(Extension(db))
         .with_state((shared_sys, shared_config.clone()));
 
-    if config.password_hash.is_some() {
+    if config.password_hash.is_some() || config.metrics_token.is_some() {
         app = auth::apply_auth_middleware(app, shared_config.clone());
+    }
+    if config.password_hash.is_some() {
         info!("Running with authentication");
     } else {
         info!("Running without authentication");
     }
+    if config.metrics_token.is_some() {
+        info!("Metrics endpoint accepts bearer token authentication");
+    }
     app = app.layer(CompressionLayer::new());
 
     info!(
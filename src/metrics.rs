@@ -0,0 +1,315 @@
+use crate::models::{DockerInfo, GeneralInfo};
+use std::fmt::Write;
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Helper for writing metric families in the Prometheus text format
+struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    fn new() -> Self {
+        MetricsWriter { out: String::new() }
+    }
+
+    /// Write the HELP and TYPE lines of a metric family
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    /// Write a single sample of a metric family
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (key, val)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                let _ = write!(self.out, "{}=\"{}\"", key, escape_label_value(val));
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", format_value(value));
+    }
+}
+
+/// Escape a label value as required by the exposition format
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Format a sample value, spelling out the special float values
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Render system and container metrics in the Prometheus text exposition format
+pub fn render(general_info: &GeneralInfo, docker_info: Option<&DockerInfo>) -> String {
+    let mut w = MetricsWriter::new();
+
+    // System
+    w.family(
+        "simon_system_info",
+        "gauge",
+        "Host information, value is always 1",
+    );
+    w.sample(
+        "simon_system_info",
+        &[
+            ("host_name", &general_info.sys.host_name),
+            ("os_name", &general_info.sys.os_name),
+            ("kernel_version", &general_info.sys.kernel_ver),
+        ],
+        1.0,
+    );
+    w.family("simon_uptime_seconds", "gauge", "System uptime in seconds");
+    w.sample("simon_uptime_seconds", &[], general_info.sys.uptime as f64);
+
+    w.family(
+        "simon_load_average",
+        "gauge",
+        "System load average over the given period",
+    );
+    for (period, value) in ["1m", "5m", "15m"].iter().zip(&general_info.sys.load_avg) {
+        w.sample("simon_load_average", &[("period", period)], *value);
+    }
+
+    // CPU
+    w.family("simon_cpu_count", "gauge", "Number of logical CPU cores");
+    w.sample("simon_cpu_count", &[], general_info.cpu.count as f64);
+    w.family(
+        "simon_cpu_usage_average_percent",
+        "gauge",
+        "Average CPU usage across all cores in percent",
+    );
+    w.sample(
+        "simon_cpu_usage_average_percent",
+        &[],
+        general_info.cpu.avg_usage as f64,
+    );
+    w.family(
+        "simon_cpu_usage_percent",
+        "gauge",
+        "CPU usage per core in percent",
+    );
+    for (core, usage) in general_info.cpu.usage.iter().enumerate() {
+        w.sample(
+            "simon_cpu_usage_percent",
+            &[("core", &core.to_string())],
+            *usage as f64,
+        );
+    }
+
+    // Memory
+    w.family(
+        "simon_memory_total_bytes",
+        "gauge",
+        "Total physical memory in bytes",
+    );
+    w.sample(
+        "simon_memory_total_bytes",
+        &[],
+        general_info.mem.total_mem as f64,
+    );
+    w.family(
+        "simon_memory_used_bytes",
+        "gauge",
+        "Used physical memory in bytes",
+    );
+    w.sample(
+        "simon_memory_used_bytes",
+        &[],
+        general_info.mem.used_mem as f64,
+    );
+    w.family(
+        "simon_swap_total_bytes",
+        "gauge",
+        "Total swap space in bytes",
+    );
+    w.sample(
+        "simon_swap_total_bytes",
+        &[],
+        general_info.mem.total_swap as f64,
+    );
+    w.family("simon_swap_used_bytes", "gauge", "Used swap space in bytes");
+    w.sample(
+        "simon_swap_used_bytes",
+        &[],
+        general_info.mem.used_swap as f64,
+    );
+
+    // Network
+    w.family(
+        "simon_network_receive_bytes_total",
+        "counter",
+        "Total bytes received per network interface",
+    );
+    for interface in &general_info.net.interfaces {
+        w.sample(
+            "simon_network_receive_bytes_total",
+            &[("interface", &interface.name)],
+            interface.rx as f64,
+        );
+    }
+    w.family(
+        "simon_network_transmit_bytes_total",
+        "counter",
+        "Total bytes transmitted per network interface",
+    );
+    for interface in &general_info.net.interfaces {
+        w.sample(
+            "simon_network_transmit_bytes_total",
+            &[("interface", &interface.name)],
+            interface.tx as f64,
+        );
+    }
+
+    // Disks
+    let disk_metrics: [(&str, &str, &str); 4] = [
+        (
+            "simon_disk_total_bytes",
+            "gauge",
+            "Total disk space in bytes",
+        ),
+        (
+            "simon_disk_free_bytes",
+            "gauge",
+            "Available disk space in bytes",
+        ),
+        (
+            "simon_disk_read_bytes_total",
+            "counter",
+            "Total bytes read from the disk",
+        ),
+        (
+            "simon_disk_written_bytes_total",
+            "counter",
+            "Total bytes written to the disk",
+        ),
+    ];
+    for (idx, (name, kind, help)) in disk_metrics.iter().enumerate() {
+        w.family(name, kind, help);
+        for disk in &general_info.disk.disks {
+            let value = match idx {
+                0 => disk.total_space,
+                1 => disk.free_space,
+                2 => disk.io[2],
+                _ => disk.io[3],
+            };
+            w.sample(
+                name,
+                &[("mount_point", &disk.mount_point), ("fs", &disk.fs)],
+                value as f64,
+            );
+        }
+    }
+
+    // Docker containers
+    if let Some(docker_info) = docker_info {
+        w.family(
+            "simon_container_info",
+            "gauge",
+            "Container information, value is always 1",
+        );
+        for container in &docker_info.containers {
+            w.sample(
+                "simon_container_info",
+                &[
+                    ("id", &container.id),
+                    ("name", container.name.trim_start_matches('/')),
+                    ("image", &container.image),
+                    ("state", &container.state),
+                ],
+                1.0,
+            );
+        }
+
+        let container_metrics: [(&str, &str, &str); 5] = [
+            (
+                "simon_container_cpu_usage_percent",
+                "gauge",
+                "Container CPU usage in percent of a single core",
+            ),
+            (
+                "simon_container_memory_usage_bytes",
+                "gauge",
+                "Container memory usage in bytes",
+            ),
+            (
+                "simon_container_memory_limit_bytes",
+                "gauge",
+                "Container memory limit in bytes",
+            ),
+            (
+                "simon_container_network_receive_bytes_total",
+                "counter",
+                "Total bytes received by the container",
+            ),
+            (
+                "simon_container_network_transmit_bytes_total",
+                "counter",
+                "Total bytes transmitted by the container",
+            ),
+        ];
+        for (idx, (name, kind, help)) in container_metrics.iter().enumerate() {
+            w.family(name, kind, help);
+            for container in &docker_info.containers {
+                let value = match idx {
+                    0 => container.cpu_usage,
+                    1 => container.mem_usage as f64,
+                    2 => container.mem_limit as f64,
+                    3 => container.net_io[0] as f64,
+                    _ => container.net_io[1] as f64,
+                };
+                w.sample(
+                    name,
+                    &[
+                        ("id", &container.id),
+                        ("name", container.name.trim_start_matches('/')),
+                    ],
+                    value,
+                );
+            }
+        }
+    }
+
+    w.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_formatting() {
+        let mut w = MetricsWriter::new();
+        w.family("simon_test", "gauge", "Test metric");
+        w.sample("simon_test", &[("name", "a \"quoted\"\\path\n")], 1.5);
+        w.sample("simon_test", &[], f64::NAN);
+        w.sample("simon_test", &[], f64::INFINITY);
+
+        assert_eq!(
+            w.out,
+            "# HELP simon_test Test metric\n\
+             # TYPE simon_test gauge\n\
+             simon_test{name=\"a \\\"quoted\\\"\\\\path\\n\"} 1.5\n\
+             simon_test NaN\n\
+             simon_test +Inf\n"
+        );
+    }
+}
@@ -0,0 +1,722 @@
+use crate::db::DbError;
+use log::{info, warn};
+use rusqlite::{Connection, Result, Transaction, TransactionBehavior, params};
+use serde_json::Value;
+
+/// A single step of the database schema history.
+/// Steps are never edited once released, schema changes get a new step instead.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    up: fn(&Transaction) -> Result<()>,
+}
+
+const MIGRATIONS: [Migration; 15] = [
+    Migration {
+        version: 1,
+        description: "Baseline time-series and key-value tables",
+        up: baseline,
+    },
+    Migration {
+        version: 2,
+        description: "Min, max and p95 columns of rolled-up metrics",
+        up: rollup_statistics,
+    },
+    Migration {
+        version: 3,
+        description: "Rollup progress tracking",
+        up: rollup_state,
+    },
+    Migration {
+        version: 4,
+        description: "Alerts and notification methods tables",
+        up: alert_tables,
+    },
+    Migration {
+        version: 5,
+        description: "Alert event history",
+        up: alert_events,
+    },
+    Migration {
+        version: 6,
+        description: "Alert recovery thresholds",
+        up: alert_recovery_threshold,
+    },
+    Migration {
+        version: 7,
+        description: "Rate of change and forecast alerts",
+        up: alert_kinds,
+    },
+    Migration {
+        version: 8,
+        description: "Composite alert conditions",
+        up: alert_conditions,
+    },
+    Migration {
+        version: 9,
+        description: "Alert silences",
+        up: silences,
+    },
+    Migration {
+        version: 10,
+        description: "Repeated and escalated alert notifications",
+        up: alert_escalations,
+    },
+    Migration {
+        version: 11,
+        description: "Alert window resolution",
+        up: alert_resolution,
+    },
+    Migration {
+        version: 12,
+        description: "Alert names and message templates",
+        up: message_templates,
+    },
+    Migration {
+        version: 13,
+        description: "Notification delivery queue and attempts",
+        up: notification_queue,
+    },
+    Migration {
+        version: 14,
+        description: "Grouped and digest notifications",
+        up: notification_batches,
+    },
+    Migration {
+        version: 15,
+        description: "Fractional alert windows",
+        up: alert_window_minutes,
+    },
+];
+
+/// Schema version this binary works with
+pub const LATEST_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Bring the database schema up to date.
+/// All pending steps run in a single transaction, so a failed upgrade leaves the database untouched.
+pub fn migrate(conn: &mut Connection) -> std::result::Result<(), DbError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Most connections find the schema up to date, don't take the write lock for them
+    if check_version(conn)? == LATEST_VERSION {
+        return Ok(());
+    }
+
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    // Another connection may have upgraded the schema while waiting for the lock
+    let current = check_version(&tx)?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!(
+            "Migrating database schema to version {}: {}",
+            migration.version, migration.description
+        );
+        (migration.up)(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_version (version, applied_at) VALUES (?, strftime('%s', 'now'))",
+            params![migration.version],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Current schema version, refusing schemas written by a newer binary
+fn check_version(conn: &Connection) -> std::result::Result<u32, DbError> {
+    let version: u32 = conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )?;
+    if version > LATEST_VERSION {
+        return Err(DbError::NewerSchema {
+            found: version,
+            supported: LATEST_VERSION,
+        });
+    }
+    Ok(version)
+}
+
+/// Version 1, the layout from before migrations were tracked.
+/// Uses IF NOT EXISTS so databases created back then are adopted as they are.
+fn baseline(tx: &Transaction) -> Result<()> {
+    for table_name in ["general_s", "general_m", "general_h", "general_d"] {
+        tx.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id INTEGER PRIMARY KEY,
+                    timestamp INTEGER,
+                    cpu_usage REAL,
+                    mem_usage REAL,
+                    swap_usage REAL,
+                    load_avg_1 REAL,
+                    load_avg_5 REAL,
+                    load_avg_15 REAL
+                )",
+                table_name
+            ),
+            [],
+        )?;
+    }
+
+    for table_name in ["net_s", "net_m", "net_h", "net_d"] {
+        tx.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id INTEGER PRIMARY KEY,
+                    timestamp INTEGER,
+                    name TEXT,
+                    rx REAL,
+                    tx REAL,
+                    rx_rate REAL,
+                    tx_rate REAL
+                )",
+                table_name
+            ),
+            [],
+        )?;
+    }
+
+    for table_name in ["disk_s", "disk_m", "disk_h", "disk_d"] {
+        tx.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id INTEGER PRIMARY KEY,
+                    timestamp INTEGER,
+                    name TEXT,
+                    total_read REAL,
+                    total_write REAL,
+                    read_rate REAL,
+                    write_rate REAL,
+                    disk_usage REAL
+                )",
+                table_name
+            ),
+            [],
+        )?;
+    }
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS kv (
+            key TEXT PRIMARY KEY,
+            value BLOB
+        )",
+        [],
+    )?;
+
+    // Timestamp indexes for all tables
+    for cat in ["general", "net", "disk"] {
+        for suffix in ["s", "m", "h", "d"] {
+            tx.execute(
+                &format!(
+                    "CREATE INDEX IF NOT EXISTS idx_{cat}_{suffix}_timestamp ON {cat}_{suffix} (timestamp)"
+                ),
+                [],
+            )?;
+        }
+    }
+
+    // Compound indexes for network and disk tables to optimize queries by name and timestamp
+    for cat in ["net", "disk"] {
+        for suffix in ["s", "m", "h", "d"] {
+            tx.execute(
+                &format!(
+                    "CREATE INDEX IF NOT EXISTS idx_{cat}_{suffix}_name_timestamp ON {cat}_{suffix} (name, timestamp)"
+                ),
+                [],
+            )?;
+        }
+    }
+
+    tx.execute("CREATE INDEX IF NOT EXISTS idx_kv_key ON kv (key)", [])?;
+    Ok(())
+}
+
+/// Version 2, statistics next to the averages of rolled-up tables
+fn rollup_statistics(tx: &Transaction) -> Result<()> {
+    let metrics: [(&str, &[&str]); 3] = [
+        (
+            "general",
+            &[
+                "cpu_usage",
+                "mem_usage",
+                "swap_usage",
+                "load_avg_1",
+                "load_avg_5",
+                "load_avg_15",
+            ],
+        ),
+        ("net", &["rx_rate", "tx_rate"]),
+        ("disk", &["read_rate", "write_rate", "disk_usage"]),
+    ];
+    for (cat, metrics) in metrics {
+        for suffix in ["m", "h", "d"] {
+            let table = format!("{}_{}", cat, suffix);
+            // Builds from before versioning may have added some of them already
+            let existing: Vec<String> = tx
+                .prepare(&format!("PRAGMA table_info({})", table))?
+                .query_map([], |row| row.get(1))?
+                .collect::<Result<_>>()?;
+            for metric in metrics {
+                for stat in ["min", "max", "p95"] {
+                    let column = format!("{}_{}", metric, stat);
+                    if !existing.contains(&column) {
+                        tx.execute(
+                            &format!("ALTER TABLE {} ADD COLUMN {} REAL", table, column),
+                            [],
+                        )?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Version 3, end of the last bucket written to each rolled-up table
+fn rollup_state(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS rollup_state (
+            tbl TEXT PRIMARY KEY,
+            last_bucket INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 4, alerts and notification methods as rows instead of JSON lists in `kv`
+fn alert_tables(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE notification_methods (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            enabled INTEGER NOT NULL,
+            config TEXT NOT NULL
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE TABLE alerts (
+            id TEXT PRIMARY KEY,
+            cat TEXT NOT NULL,
+            var TEXT NOT NULL,
+            resrc TEXT NOT NULL,
+            threshold REAL NOT NULL,
+            operator TEXT NOT NULL,
+            time_window INTEGER NOT NULL,
+            enabled INTEGER NOT NULL,
+            firing INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE TABLE alert_notif_methods (
+            alert_id TEXT NOT NULL REFERENCES alerts (id) ON DELETE CASCADE,
+            method_id TEXT NOT NULL REFERENCES notification_methods (id) ON DELETE CASCADE,
+            PRIMARY KEY (alert_id, method_id)
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX idx_alert_notif_methods_method ON alert_notif_methods (method_id)",
+        [],
+    )?;
+
+    // Move over the lists kept in kv by older versions.
+    // They are read as plain JSON so this step doesn't change along with the models.
+    if let Some(methods) = legacy_list(tx, "notification_methods")? {
+        for method in &methods {
+            let (Some(id), Some(config)) = (method["id"].as_str(), method.get("config")) else {
+                warn!("Skipping invalid notification method: {}", method);
+                continue;
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO notification_methods (id, name, kind, enabled, config)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![
+                    id,
+                    method["name"].as_str().unwrap_or_default(),
+                    method["kind"].as_str().unwrap_or_default(),
+                    method["enabled"].as_bool().unwrap_or(true),
+                    config.to_string()
+                ],
+            )?;
+        }
+        tx.execute("DELETE FROM kv WHERE key = 'notification_methods'", [])?;
+    }
+
+    if let Some(alerts) = legacy_list(tx, "alerts")? {
+        for alert in &alerts {
+            let var = &alert["var"];
+            let (Some(id), Some(cat), Some(name), Some(threshold), Some(operator)) = (
+                alert["id"].as_str(),
+                var["cat"].as_str(),
+                var["var"].as_str(),
+                alert["threshold"].as_f64(),
+                alert["operator"].as_str(),
+            ) else {
+                warn!("Skipping invalid alert: {}", alert);
+                continue;
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO alerts
+                    (id, cat, var, resrc, threshold, operator, time_window, enabled, firing)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    id,
+                    cat,
+                    name,
+                    var["resrc"].as_str().unwrap_or_default(),
+                    threshold,
+                    operator,
+                    alert["time_window"].as_i64().unwrap_or(1),
+                    alert["enabled"].as_bool().unwrap_or(true),
+                    alert["firing"].as_bool().unwrap_or(false)
+                ],
+            )?;
+            // Links to methods that no longer exist are dropped
+            for method_id in alert["notif_methods"].as_array().into_iter().flatten() {
+                tx.execute(
+                    "INSERT OR IGNORE INTO alert_notif_methods (alert_id, method_id)
+                     SELECT ?1, id FROM notification_methods WHERE id = ?2",
+                    params![id, method_id.as_str().unwrap_or_default()],
+                )?;
+            }
+        }
+        tx.execute("DELETE FROM kv WHERE key = 'alerts'", [])?;
+    }
+    Ok(())
+}
+
+/// Version 5, history of alert transitions and of the notifications they sent.
+/// Events outlive their alert so the history stays readable after it's deleted.
+fn alert_events(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE alert_events (
+            id INTEGER PRIMARY KEY,
+            alert_id TEXT NOT NULL,
+            t INTEGER NOT NULL,
+            transition TEXT NOT NULL,
+            value REAL,
+            threshold REAL NOT NULL,
+            message TEXT NOT NULL
+        )",
+        [],
+    )?;
+    tx.execute("CREATE INDEX idx_alert_events_t ON alert_events (t)", [])?;
+    tx.execute(
+        "CREATE INDEX idx_alert_events_alert_t ON alert_events (alert_id, t)",
+        [],
+    )?;
+    tx.execute(
+        "CREATE TABLE alert_event_deliveries (
+            id INTEGER PRIMARY KEY,
+            event_id INTEGER NOT NULL REFERENCES alert_events (id) ON DELETE CASCADE,
+            method_id TEXT NOT NULL,
+            method_name TEXT NOT NULL,
+            t INTEGER NOT NULL,
+            success INTEGER NOT NULL,
+            error TEXT
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX idx_alert_event_deliveries_event ON alert_event_deliveries (event_id)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 6, separate threshold for resolving alerts
+fn alert_recovery_threshold(tx: &Transaction) -> Result<()> {
+    tx.execute("ALTER TABLE alerts ADD COLUMN recovery_threshold REAL", [])?;
+    Ok(())
+}
+
+/// Version 7, alerts on trends in addition to plain thresholds
+fn alert_kinds(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "ALTER TABLE alerts ADD COLUMN kind TEXT NOT NULL DEFAULT 'threshold'",
+        [],
+    )?;
+    tx.execute("ALTER TABLE alerts ADD COLUMN horizon REAL", [])?;
+    Ok(())
+}
+
+/// Version 8, boolean expression over several metrics stored as JSON
+fn alert_conditions(tx: &Transaction) -> Result<()> {
+    tx.execute("ALTER TABLE alerts ADD COLUMN condition TEXT", [])?;
+    Ok(())
+}
+
+/// Version 9, silences and the events they muted
+fn silences(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE silences (
+            id TEXT PRIMARY KEY,
+            comment TEXT NOT NULL,
+            alert_ids TEXT NOT NULL,
+            cat TEXT,
+            resrc TEXT,
+            start_time INTEGER NOT NULL,
+            end_time INTEGER,
+            recurrence TEXT
+        )",
+        [],
+    )?;
+    tx.execute("ALTER TABLE alert_events ADD COLUMN silenced_by TEXT", [])?;
+    Ok(())
+}
+
+/// Version 10, notification settings and the state they need across restarts
+fn alert_escalations(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE alerts ADD COLUMN renotify_interval INTEGER;
+         ALTER TABLE alerts ADD COLUMN escalations TEXT NOT NULL DEFAULT '[]';
+         ALTER TABLE alerts ADD COLUMN fired_at INTEGER;
+         ALTER TABLE alerts ADD COLUMN last_notified INTEGER;
+         ALTER TABLE alerts ADD COLUMN escalation_level INTEGER NOT NULL DEFAULT 0;",
+    )?;
+    Ok(())
+}
+
+/// Version 11, data table alerts are evaluated on.
+/// Windows become fractional minutes too, which the INTEGER column stores as they are
+/// until version 15.
+fn alert_resolution(tx: &Transaction) -> Result<()> {
+    tx.execute("ALTER TABLE alerts ADD COLUMN resolution TEXT", [])?;
+    Ok(())
+}
+
+/// Version 12, alert names and message templates of alerts and notification methods
+fn message_templates(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE alerts ADD COLUMN name TEXT NOT NULL DEFAULT '';
+         ALTER TABLE alerts ADD COLUMN template TEXT;
+         ALTER TABLE notification_methods ADD COLUMN template TEXT;",
+    )?;
+    Ok(())
+}
+
+/// Version 13, retries of failed notifications. Each attempt is a delivery of its event,
+/// the pending ones are queued along with what they need to be sent again.
+fn notification_queue(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE notification_methods ADD COLUMN timeout INTEGER NOT NULL DEFAULT 30;
+         ALTER TABLE notification_methods ADD COLUMN max_retries INTEGER NOT NULL DEFAULT 5;
+         ALTER TABLE alert_event_deliveries ADD COLUMN attempt INTEGER NOT NULL DEFAULT 1;
+         ALTER TABLE alert_event_deliveries ADD COLUMN status INTEGER;
+         ALTER TABLE alert_event_deliveries ADD COLUMN response TEXT;
+         ALTER TABLE alert_event_deliveries ADD COLUMN retry_at INTEGER;
+         CREATE INDEX idx_alert_event_deliveries_t ON alert_event_deliveries (t);
+         CREATE TABLE notification_queue (
+             id INTEGER PRIMARY KEY,
+             event_id INTEGER REFERENCES alert_events (id) ON DELETE CASCADE,
+             method_id TEXT NOT NULL REFERENCES notification_methods (id) ON DELETE CASCADE,
+             template TEXT,
+             context TEXT NOT NULL,
+             attempts INTEGER NOT NULL DEFAULT 0,
+             next_attempt INTEGER NOT NULL
+         );
+         CREATE INDEX idx_notification_queue_next ON notification_queue (next_attempt);",
+    )?;
+    Ok(())
+}
+
+/// Version 14, notifications sent together: of the same alert check or collected into a digest
+fn notification_batches(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE notification_methods ADD COLUMN grouping INTEGER NOT NULL DEFAULT 0;
+         ALTER TABLE notification_methods ADD COLUMN digest_interval INTEGER;
+         ALTER TABLE notification_queue ADD COLUMN batch TEXT;",
+    )?;
+    Ok(())
+}
+
+/// Version 15, alert windows in a REAL column, as they can be fractions of a minute.
+/// SQLite can't change the type of a column, so it's replaced by a new one.
+fn alert_window_minutes(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE alerts ADD COLUMN window_minutes REAL NOT NULL DEFAULT 1;
+         UPDATE alerts SET window_minutes = time_window;
+         ALTER TABLE alerts DROP COLUMN time_window;
+         ALTER TABLE alerts RENAME COLUMN window_minutes TO time_window;",
+    )?;
+    Ok(())
+}
+
+/// JSON list stored under a kv key by older versions.
+/// Unreadable lists are left in place so they can be recovered by hand.
+fn legacy_list(tx: &Transaction, key: &str) -> Result<Option<Vec<Value>>> {
+    let json: Option<String> = tx
+        .query_row("SELECT value FROM kv WHERE key = ?", params![key], |row| {
+            row.get(0)
+        })
+        .ok();
+    let Some(json) = json else {
+        return Ok(None);
+    };
+    match serde_json::from_str::<Vec<Value>>(&json) {
+        Ok(list) => {
+            info!("Migrating {} {} from kv", list.len(), key);
+            Ok(Some(list))
+        }
+        Err(e) => {
+            warn!("Can't migrate {} from kv, keeping it as is: {}", key, e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+
+    fn temp_db_path() -> String {
+        std::env::temp_dir()
+            .join(format!("simon-test-{}.db", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn remove_db(path: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    fn schema_version(db: &Database) -> u32 {
+        let conn = db.conn.lock().unwrap();
+        check_version(&conn).unwrap()
+    }
+
+    #[test]
+    fn test_upgrade_baseline_database() {
+        let path = temp_db_path();
+        {
+            // Database as created before migrations existed
+            let mut conn = Connection::open(&path).unwrap();
+            let tx = conn.transaction().unwrap();
+            baseline(&tx).unwrap();
+            tx.execute(
+                "INSERT INTO general_m (timestamp, cpu_usage) VALUES (60, 42.5)",
+                [],
+            )
+            .unwrap();
+            tx.execute(
+                "INSERT INTO kv (key, value) VALUES ('jwt_secret', 'abc')",
+                [],
+            )
+            .unwrap();
+            tx.commit().unwrap();
+        }
+
+        let db = Database::new(&path).unwrap();
+        assert_eq!(schema_version(&db), LATEST_VERSION);
+        assert_eq!(db.get_kv_str("jwt_secret").unwrap().as_deref(), Some("abc"));
+        {
+            let conn = db.conn.lock().unwrap();
+            let row: (f64, Option<f64>) = conn
+                .query_row(
+                    "SELECT cpu_usage, cpu_usage_max FROM general_m WHERE timestamp = 60",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+            assert_eq!(row, (42.5, None));
+            conn.query_row("SELECT COUNT(*) FROM rollup_state", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap();
+        }
+        drop(db);
+
+        // Opening it again is a no-op
+        let db = Database::new(&path).unwrap();
+        assert_eq!(schema_version(&db), LATEST_VERSION);
+        drop(db);
+        remove_db(&path);
+    }
+
+    #[test]
+    fn test_refuse_newer_schema() {
+        let path = temp_db_path();
+        drop(Database::new(&path).unwrap());
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute(
+                "INSERT INTO schema_version (version, applied_at) VALUES (?, 0)",
+                params![LATEST_VERSION + 1],
+            )
+            .unwrap();
+        }
+
+        assert!(matches!(
+            Database::new(&path),
+            Err(DbError::NewerSchema { found, .. }) if found == LATEST_VERSION + 1
+        ));
+        remove_db(&path);
+    }
+
+    #[test]
+    fn test_migrate_alerts_from_kv() {
+        let path = temp_db_path();
+        {
+            let mut conn = Connection::open(&path).unwrap();
+            let tx = conn.transaction().unwrap();
+            baseline(&tx).unwrap();
+            tx.execute(
+                "INSERT INTO kv (key, value) VALUES ('notification_methods', ?)",
+                params![
+                    r#"[{"id":"m1","name":"hook","kind":"webhook","enabled":true,
+                        "config":{"WebHook":{"url":"http://x/{notif_msg}","method":"GET","headers":{},"body":""}}}]"#
+                ],
+            )
+            .unwrap();
+            tx.execute(
+                "INSERT INTO kv (key, value) VALUES ('alerts', ?)",
+                params![
+                    r#"[{"id":"a1","var":{"cat":"sys","var":"cpu_usage","resrc":"sys"},"threshold":90.0,
+                        "operator":">","time_window":5,"enabled":true,"firing":true,"notif_methods":["m1","gone"]}]"#
+                ],
+            )
+            .unwrap();
+            tx.commit().unwrap();
+        }
+
+        let db = Database::new(&path).unwrap();
+        let alerts = db.get_alerts().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].var.var, "cpu_usage");
+        assert!(alerts[0].firing);
+        assert_eq!(alerts[0].notif_methods, vec!["m1"]);
+        assert_eq!(db.get_notification_methods().unwrap().len(), 1);
+        assert_eq!(db.get_kv_str("alerts").unwrap(), None);
+
+        // Deleting a method unlinks it from its alerts
+        assert!(db.delete_notification_method("m1").unwrap());
+        assert!(db.get_alerts().unwrap()[0].notif_methods.is_empty());
+        drop(db);
+
+        // Windows moved to a REAL column
+        let conn = Connection::open(&path).unwrap();
+        let (window, kind): (f64, String) = conn
+            .query_row(
+                "SELECT time_window, typeof(time_window) FROM alerts",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((window, kind.as_str()), (5.0, "real"));
+        drop(conn);
+        remove_db(&path);
+    }
+}
@@ -14,7 +14,7 @@ pub struct SystemCapabilities {
     pub file_serving: bool,
 }
 
-pub const ALERT_VARIABLES: [(&str, &str); 11] = [
+pub const ALERT_VARIABLES: [(&str, &str); 19] = [
     ("sys", "cpu_usage"),
     ("sys", "mem_usage"),
     ("sys", "swap_usage"),
@@ -26,6 +26,14 @@ pub const ALERT_VARIABLES: [(&str, &str); 11] = [
     ("disk", "read_rate"),
     ("disk", "write_rate"),
     ("disk", "disk_usage"),
+    ("docker", "running"),
+    ("docker", "restarts"),
+    ("docker", "cpu_usage"),
+    ("docker", "mem_usage"),
+    ("docker", "unhealthy"),
+    ("proc", "count"),
+    ("proc", "cpu_usage"),
+    ("proc", "mem"),
 ];
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -123,7 +131,7 @@ pub struct ProcessesInfo {
     pub processes: Vec<ProcessInfo>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, PartialEq, Serialize)]
 pub struct ProcessInfo {
     pub pid: u32,
     pub runtime: u64,
@@ -135,7 +143,7 @@ pub struct ProcessInfo {
     pub env: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct DockerContainer {
     pub id: String,
     pub name: String,
@@ -143,6 +151,8 @@ pub struct DockerContainer {
     pub status: String,
     pub state: String,
     pub created: i64,
+    pub restart_count: i64,
+    pub health: String, // Empty without a healthcheck
     pub ports: Vec<DockerPort>,
     pub cpu_usage: f64,
     pub mem_usage: u64,
@@ -151,7 +161,7 @@ pub struct DockerContainer {
     pub disk_io: [u64; 2],
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct DockerPort {
     pub ip: Option<String>,
     pub priv_port: u16,
@@ -165,6 +175,29 @@ pub struct DockerInfo {
     pub containers: Vec<DockerContainer>,
 }
 
+/// Control messages sent by clients of the multiplexed websocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum WsClientMessage {
+    Subscribe {
+        topic: String,
+        id: Option<String>,    // Container id or name for the "container" topic
+        interval: Option<u64>, // Minimum seconds between updates
+    },
+    Unsubscribe {
+        topic: String,
+        id: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertStateChange {
+    pub t: i64,
+    pub alert_id: String,
+    pub firing: bool,
+    pub message: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HistoricalSeries {
     pub cat: String,
@@ -179,22 +212,209 @@ pub struct HistoricalQueryOptions {
     pub start_time: Option<i64>,
     pub end_time: Option<i64>,
     pub limit: Option<i64>,
-    pub resolution: String, // "second", "minute", "hour", "day"
+    pub resolution: String,  // "second", "minute", "hour", "day"
+    pub agg: Option<String>, // "avg" (default), "min", "max", "p95"
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbStats {
+    pub file_size: i64,
+    pub free_size: i64,
+    pub tables: Vec<TableStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableStats {
+    pub name: String,
+    pub rows: i64,
+    pub oldest: Option<i64>,
+    pub size: Option<i64>, // Bytes used by the table and its indexes
+    pub retention: u64,    // Seconds, 0 if kept forever
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Alert {
     pub id: String,
+    #[serde(default)]
+    pub name: String, // Generated from the condition when empty
+    #[serde(default)]
     pub var: AlertVar,
+    #[serde(default = "default_alert_kind")]
+    pub kind: String, // "threshold", "rate" (change per hour) or "forecast"
+    #[serde(default)]
     pub threshold: f64,
+    #[serde(default)]
+    pub recovery_threshold: Option<f64>, // Threshold to cross back before resolving
+    #[serde(default)]
+    pub horizon: Option<f64>, // Hours ahead checked by forecast alerts
+    #[serde(default)]
     pub operator: String,
-    pub time_window: i64,
+    #[serde(default)]
+    pub time_window: f64, // Minutes, fractions allow windows shorter than a minute
+    #[serde(default)]
+    pub resolution: Option<String>, // "second", "minute" or "hour", picked from the window if unset
+    #[serde(default)]
+    pub condition: Option<AlertCondition>, // Replaces the single metric check when set
     pub enabled: bool,
     pub firing: bool,
     pub notif_methods: Vec<String>,
+    #[serde(default)]
+    pub renotify_interval: Option<i64>, // Minutes between repeated notifications while firing
+    #[serde(default)]
+    pub escalations: Vec<AlertEscalation>, // Ordered by delay
+    #[serde(default)]
+    pub fired_at: Option<i64>,
+    #[serde(default)]
+    pub last_notified: Option<i64>,
+    #[serde(default)]
+    pub escalation_level: u32, // Number of escalations already notified
+    #[serde(default)]
+    pub template: Option<String>, // Message template, overrides the one of the methods
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Further notification methods to notify once an alert has been firing for a while
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertEscalation {
+    pub after: i64, // Minutes since the alert fired
+    pub notif_methods: Vec<String>,
+}
+
+fn default_alert_kind() -> String {
+    "threshold".to_string()
+}
+
+/// Boolean expression over metric checks, evaluated as a single alert
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum AlertCondition {
+    And { conditions: Vec<AlertCondition> },
+    Or { conditions: Vec<AlertCondition> },
+    Not { condition: Box<AlertCondition> },
+    Metric(AlertCheck),
+}
+
+/// Check of a single metric within a composite alert
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertCheck {
+    pub var: AlertVar,
+    #[serde(default = "default_alert_kind")]
+    pub kind: String,
+    pub threshold: f64,
+    #[serde(default)]
+    pub horizon: Option<f64>,
+    pub operator: String,
+    pub time_window: f64,
+    #[serde(default)]
+    pub resolution: Option<String>,
+}
+
+/// Recorded state transition of an alert
+#[derive(Debug, Serialize)]
+pub struct AlertEvent {
+    pub id: i64,
+    pub alert_id: String,
+    pub t: i64,
+    pub transition: String, // "fired", "resolved", "renotified" or "escalated"
+    pub value: Option<f64>, // Aggregate that was compared to the threshold
+    pub threshold: f64,
+    pub message: String,
+    pub silenced_by: Option<String>, // Silence that suppressed the notifications
+    pub deliveries: Vec<AlertDelivery>,
+}
+
+/// Outcome of an attempt at sending an alert event through a notification method
+#[derive(Debug, Serialize)]
+pub struct AlertDelivery {
+    pub method_id: String,
+    pub method_name: String,
+    pub t: i64,
+    pub success: bool,
+    pub error: Option<String>,
+    pub attempt: u32,             // Starting at 1
+    pub status: Option<u16>,      // HTTP status of webhooks, SMTP reply code of emails
+    pub response: Option<String>, // Start of the response body, or of the program output
+    pub retry_at: Option<i64>,    // When the failed delivery is attempted again
+}
+
+/// Outcome of a test notification, returned to whoever asked for it
+#[derive(Debug, Serialize)]
+pub struct NotificationTestResult {
+    pub success: bool,
+    pub error: Option<String>,
+    pub status: Option<u16>, // HTTP status of webhooks, SMTP reply code of emails
+    pub response: Option<String>, // Start of the response body, or of the program output
+    pub latency_ms: u64,
+}
+
+/// Entry of the delivery log, an attempt along with the alert event it was for
+#[derive(Debug, Serialize)]
+pub struct DeliveryLogEntry {
+    pub event_id: i64,
+    pub alert_id: String,
+    pub transition: String,
+    #[serde(flatten)]
+    pub delivery: AlertDelivery,
+}
+
+/// Notification waiting in the delivery queue, kept across restarts
+#[derive(Debug)]
+pub struct QueuedNotification {
+    pub id: i64,
+    pub event_id: Option<i64>,
+    pub method_id: String,
+    pub template: Option<String>,   // Message template of the alert
+    pub context: serde_json::Value, // Variables of the alert
+    pub attempts: u32,
+    pub next_attempt: i64,
+    pub batch: Option<String>, // Notifications of the method in the same batch are sent together
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryLogQuery {
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub method_id: Option<String>,
+    pub alert_id: Option<String>,
+    pub success: Option<bool>,
+    pub limit: Option<i64>,
+}
+
+/// Time-bounded mute of the notifications of matching alerts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Silence {
+    pub id: String,
+    #[serde(default)]
+    pub comment: String,
+    #[serde(default)]
+    pub alert_ids: Vec<String>, // Any alert if empty
+    #[serde(default)]
+    pub cat: Option<String>, // Alerts on a variable of this category
+    #[serde(default)]
+    pub resrc: Option<String>, // Alerts on a variable of this resource
+    pub start_time: i64,
+    #[serde(default)]
+    pub end_time: Option<i64>, // Only open-ended when recurring
+    #[serde(default)]
+    pub recurrence: Option<SilenceRecurrence>,
+}
+
+/// Weekly window within the bounds of a silence, in local time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SilenceRecurrence {
+    pub days: Vec<String>, // "mon" to "sun"
+    pub start: String,     // "HH:MM"
+    pub duration: i64,     // Minutes
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertHistoryQuery {
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub alert_id: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AlertVar {
     pub cat: String,   // Category
     pub var: String,   // variable name (ex. rx_rate)
@@ -208,11 +428,36 @@ pub struct NotificationMethod {
     pub kind: String,
     pub enabled: bool,
     pub config: NotificationConfig,
+    #[serde(default)]
+    pub template: Option<String>, // Message template, the default message if unset
+    #[serde(default = "default_notif_timeout")]
+    pub timeout: u32, // Seconds before a delivery attempt is given up
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32, // Retries of failed deliveries, further and further apart
+    #[serde(default)]
+    pub grouping: bool, // Notifications of the same alert check are sent as one message
+    #[serde(default)]
+    pub digest_interval: Option<u32>, // Minutes notifications are collected for before being sent
+}
+
+fn default_notif_timeout() -> u32 {
+    30
+}
+
+fn default_max_retries() -> u32 {
+    5
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum NotificationConfig {
     WebHook(WebHookNotif),
+    Email(EmailNotif),
+    Exec(ExecNotif),
+    Mqtt(MqttNotif),
+    Slack(SlackNotif),
+    Discord(DiscordNotif),
+    Alertmanager(AlertmanagerNotif),
+    PagerDuty(PagerDutyNotif),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -223,17 +468,99 @@ pub struct WebHookNotif {
     pub body: String,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmailNotif {
     pub server: String,
     pub port: u16,
-    pub username: String,
+    #[serde(default = "default_email_security")]
+    pub security: String, // "starttls", "tls" (implicit) or "none"
+    #[serde(default)]
+    pub username: String, // No authentication when empty
+    #[serde(default)]
     pub password: String,
     pub from: String,
-    pub to: String,
-    pub subject: String,
-    pub body: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub subject: String, // Template, a default subject when empty
+    #[serde(default)]
+    pub body: String, // Template, the message when empty
+}
+
+fn default_email_security() -> String {
+    "starttls".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExecNotif {
+    pub command: String, // Program to run, not through a shell
+    #[serde(default)]
+    pub args: Vec<String>, // Templates
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MqttNotif {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default)]
+    pub username: String, // No authentication when empty
+    #[serde(default)]
+    pub password: String,
+    pub topic: String, // Template
+    #[serde(default)]
+    pub payload: String, // Template, the variables as a JSON object when empty
+    #[serde(default = "default_mqtt_qos")]
+    pub qos: u8, // 0, 1 or 2
+    #[serde(default)]
+    pub retain: bool,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_qos() -> u8 {
+    1
+}
+
+/// Slack or Mattermost incoming webhook
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SlackNotif {
+    pub url: String,
+}
+
+/// Discord channel webhook
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiscordNotif {
+    pub url: String,
+}
+
+/// Receiver of Prometheus Alertmanager webhooks
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AlertmanagerNotif {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// PagerDuty Events API v2 integration
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PagerDutyNotif {
+    pub routing_key: String, // Integration key of the service
+    #[serde(default = "default_pagerduty_severity")]
+    pub severity: String, // "critical", "error", "warning" or "info"
+    #[serde(default = "default_pagerduty_url")]
+    pub url: String, // Events API endpoint, e.g. the EU one
+}
+
+fn default_pagerduty_severity() -> String {
+    "critical".to_string()
+}
+
+fn default_pagerduty_url() -> String {
+    "https://events.pagerduty.com/v2/enqueue".to_string()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,432 @@
+use crate::config::Config;
+use crate::hub::Hub;
+use crate::models::{GeneralInfo, MqttNotif};
+use log::{debug, error, info, warn};
+use rumqttc::{
+    AsyncClient, Event, LastWill, MqttOptions, Outgoing, Packet, QoS, TlsConfiguration, Transport,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+use sysinfo::System;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Duration, MissedTickBehavior, interval};
+
+/// Split a broker address like "host", "host:port" or "[::1]:port", the port defaulting to 1883
+pub fn parse_broker(broker: &str) -> Result<(String, u16), String> {
+    let broker = broker.trim();
+    let (host, port) = match broker.rsplit_once(':') {
+        // A bare IPv6 address has colons too
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("invalid port in \"{}\"", broker))?;
+            (host, port)
+        }
+        _ => (broker, 1883),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(format!("missing host in \"{}\"", broker));
+    }
+    Ok((host.to_string(), port))
+}
+
+pub fn qos(level: u8) -> Result<QoS, String> {
+    match level {
+        0 => Ok(QoS::AtMostOnce),
+        1 => Ok(QoS::AtLeastOnce),
+        2 => Ok(QoS::ExactlyOnce),
+        _ => Err(format!("Invalid QoS {}, expected 0, 1 or 2", level)),
+    }
+}
+
+fn options(
+    client_id: &str,
+    host: &str,
+    port: u16,
+    tls: bool,
+    username: &str,
+    password: &str,
+) -> Result<MqttOptions, String> {
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if tls {
+        // Bundled Mozilla roots like emails, minimal containers have no platform certificates
+        let roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        options.set_transport(Transport::tls_with_config(TlsConfiguration::Rustls(
+            Arc::new(config),
+        )));
+    }
+    if !username.is_empty() {
+        options.set_credentials(username, password);
+    }
+    Ok(options)
+}
+
+/// Publish a single message on a fresh connection, waiting for the broker
+/// to acknowledge it unless the QoS is 0
+pub async fn publish(notif: &MqttNotif, topic: &str, payload: String) -> Result<(), String> {
+    let qos = qos(notif.qos)?;
+    let client_id = format!("simon-{}", uuid::Uuid::new_v4().simple());
+    let options = options(
+        &client_id,
+        &notif.host,
+        notif.port,
+        notif.tls,
+        &notif.username,
+        &notif.password,
+    )?;
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    client
+        .publish(topic, qos, notif.retain, payload)
+        .await
+        .map_err(|e| format!("Failed to queue MQTT message: {}", e))?;
+
+    let delivery = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Outgoing(Outgoing::Publish(_))) if qos == QoS::AtMostOnce => {
+                    return Ok(());
+                }
+                Ok(Event::Incoming(Packet::PubAck(_) | Packet::PubComp(_))) => return Ok(()),
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(format!(
+                        "MQTT connection to {}:{} failed: {}",
+                        notif.host, notif.port, e
+                    ));
+                }
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(30), delivery)
+        .await
+        .map_err(|_| format!("MQTT broker {}:{} timed out", notif.host, notif.port))??;
+
+    // The message is delivered, a failed disconnect doesn't matter
+    let _ = client.disconnect().await;
+    let _ = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Ok(event) = eventloop.poll().await {
+            if matches!(event, Event::Outgoing(Outgoing::Disconnect)) {
+                break;
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Identifier usable in topics and Home Assistant ids: lowercase letters, digits and underscores
+fn node_id(name: &str) -> String {
+    let id: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    id.trim_matches('_').to_string()
+}
+
+/// Home Assistant discovery messages announcing the metrics of a `GeneralInfo` snapshot
+/// published at `{base}/info` as sensors, one per metric and mounted disk
+fn discovery_messages(
+    prefix: &str,
+    node: &str,
+    base: &str,
+    info: &GeneralInfo,
+) -> Vec<(String, String)> {
+    let mut sensors = vec![
+        (
+            "cpu_usage".to_string(),
+            "CPU usage".to_string(),
+            "{{ value_json.cpu.avg_usage | round(1) }}".to_string(),
+            Some("%"),
+        ),
+        (
+            "memory_usage".to_string(),
+            "Memory usage".to_string(),
+            "{{ (100 * value_json.mem.used_mem / value_json.mem.total_mem) | round(1) if value_json.mem.total_mem else 0 }}".to_string(),
+            Some("%"),
+        ),
+        (
+            "swap_usage".to_string(),
+            "Swap usage".to_string(),
+            "{{ (100 * value_json.mem.used_swap / value_json.mem.total_swap) | round(1) if value_json.mem.total_swap else 0 }}".to_string(),
+            Some("%"),
+        ),
+    ];
+    for (i, minutes) in [1, 5, 15].into_iter().enumerate() {
+        sensors.push((
+            format!("load_{}", minutes),
+            format!("Load average {} min", minutes),
+            format!("{{{{ value_json.sys.load_avg[{}] }}}}", i),
+            None,
+        ));
+    }
+    for disk in info.disk.disks.iter().filter(|d| d.total_space > 0) {
+        let object = match node_id(&disk.mount_point) {
+            id if id.is_empty() => "root".to_string(),
+            id => id,
+        };
+        // A JSON string is a valid Jinja string literal
+        let mount_point = serde_json::Value::from(disk.mount_point.as_str());
+        sensors.push((
+            format!("disk_{}", object),
+            format!("Disk usage {}", disk.mount_point),
+            format!(
+                "{{% for d in value_json.disk.disks if d.mount_point == {} %}}{{{{ (100 - 100 * d.free_space / d.total_space) | round(1) }}}}{{% endfor %}}",
+                mount_point
+            ),
+            Some("%"),
+        ));
+    }
+
+    let device = json!({
+        "identifiers": [format!("simon_{}", node)],
+        "name": info.sys.host_name,
+        "manufacturer": "Simon",
+        "model": info.sys.os_name,
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let mut messages: Vec<(String, String)> = sensors
+        .into_iter()
+        .map(|(object, name, template, unit)| {
+            let mut config = json!({
+                "name": name,
+                "unique_id": format!("simon_{}_{}", node, object),
+                "state_topic": format!("{}/info", base),
+                "value_template": template,
+                "state_class": "measurement",
+                "availability_topic": format!("{}/status", base),
+                "device": device,
+            });
+            if let Some(unit) = unit {
+                config["unit_of_measurement"] = unit.into();
+            }
+            (
+                format!("{}/sensor/{}/{}/config", prefix, node, object),
+                config.to_string(),
+            )
+        })
+        .collect();
+    messages.push((
+        format!("{}/sensor/{}/uptime/config", prefix, node),
+        json!({
+            "name": "Uptime",
+            "unique_id": format!("simon_{}_uptime", node),
+            "state_topic": format!("{}/info", base),
+            "value_template": "{{ value_json.sys.uptime }}",
+            "unit_of_measurement": "s",
+            "device_class": "duration",
+            "availability_topic": format!("{}/status", base),
+            "device": device,
+        })
+        .to_string(),
+    ));
+    messages
+}
+
+/// Queue a message without waiting, they pile up in the client while disconnected
+fn publish_json<T: Serialize>(client: &AsyncClient, topic: String, data: &T) {
+    let payload = serde_json::to_string(data).unwrap();
+    if let Err(e) = client.try_publish(&topic, QoS::AtLeastOnce, false, payload) {
+        debug!("Dropped MQTT message to {}: {}", topic, e);
+    }
+}
+
+/// Publish the metric snapshots of the hub and alert state changes to the MQTT broker of
+/// the config, along with Home Assistant discovery messages every time it connects
+pub async fn run_publisher(config: Arc<Config>, hub: Arc<Hub>) {
+    let Some(broker) = config.mqtt_broker.as_deref() else {
+        return;
+    };
+    // Checked when parsing the config
+    let (host, port) = parse_broker(broker).unwrap();
+    let node = match node_id(&System::host_name().unwrap_or_default()) {
+        id if id.is_empty() => "simon".to_string(),
+        id => id,
+    };
+    let base = format!("{}/{}", config.mqtt_topic, node);
+    let status_topic = format!("{}/status", base);
+
+    let mut options = match options(
+        &format!("simon-{}", node),
+        &host,
+        port,
+        config.mqtt_tls,
+        config.mqtt_username.as_deref().unwrap_or_default(),
+        config.mqtt_password.as_deref().unwrap_or_default(),
+    ) {
+        Ok(options) => options,
+        Err(e) => {
+            error!("MQTT publisher disabled: {}", e);
+            return;
+        }
+    };
+    options.set_last_will(LastWill::new(
+        &status_topic,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    let (client, mut eventloop) = AsyncClient::new(options, 256);
+    let mut alerts = hub.alerts.subscribe();
+    let mut general = hub.general.subscribe();
+    // Discovery messages describe the disks and interfaces of a snapshot, so after
+    // connecting they wait for the first one
+    let mut discovery_pending = false;
+    let mut ticks = interval(Duration::from_secs(config.mqtt_interval));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    info!("Publishing to MQTT broker {}:{} under {}", host, port, base);
+
+    loop {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}:{}", host, port);
+                    if let Err(e) = client.try_publish(&status_topic, QoS::AtLeastOnce, true, "online") {
+                        debug!("Dropped MQTT message to {}: {}", status_topic, e);
+                    }
+                    discovery_pending = !config.mqtt_discovery_prefix.is_empty();
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("MQTT connection to {}:{} failed: {}", host, port, e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            },
+            changed = general.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = ticks.tick() => {
+                if let Some(snapshot) = general.borrow().as_ref() {
+                    publish_json(&client, format!("{}/info", base), &snapshot.data);
+                }
+            }
+            change = alerts.recv() => match change {
+                Ok(change) => publish_json(&client, format!("{}/alert", base), &change),
+                Err(RecvError::Lagged(n)) => warn!("MQTT publisher missed {} alert changes", n),
+                Err(RecvError::Closed) => return,
+            },
+        }
+
+        if discovery_pending && let Some(snapshot) = general.borrow().clone() {
+            discovery_pending = false;
+            for (topic, payload) in
+                discovery_messages(&config.mqtt_discovery_prefix, &node, &base, &snapshot.data)
+            {
+                if let Err(e) = client.try_publish(&topic, QoS::AtLeastOnce, true, payload) {
+                    debug!("Dropped MQTT message to {}: {}", topic, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CpuInfo, DiskInfo, DisksInfo, MemoryInfo, NetworkInfo, SystemInfo};
+
+    #[test]
+    fn test_parse_broker() {
+        assert_eq!(parse_broker("broker"), Ok(("broker".to_string(), 1883)));
+        assert_eq!(
+            parse_broker(" broker.lan:8883 "),
+            Ok(("broker.lan".to_string(), 8883))
+        );
+        assert_eq!(parse_broker("[::1]:1884"), Ok(("::1".to_string(), 1884)));
+        assert_eq!(parse_broker("fe80::1"), Ok(("fe80::1".to_string(), 1883)));
+        assert!(parse_broker("broker:mqtt").is_err());
+        assert!(parse_broker(":1883").is_err());
+    }
+
+    #[test]
+    fn test_node_id() {
+        assert_eq!(node_id("My-Server.lan"), "my_server_lan");
+        assert_eq!(node_id("/mnt/data"), "mnt_data");
+        assert_eq!(node_id("/"), "");
+    }
+
+    #[test]
+    fn test_discovery_messages() {
+        let disk = |mount_point: &str, total_space| DiskInfo {
+            fs: "ext4".to_string(),
+            kind: "SSD".to_string(),
+            total_space,
+            free_space: 0,
+            mount_point: mount_point.to_string(),
+            removable: false,
+            io: [0; 4],
+        };
+        let info = GeneralInfo {
+            t: 0,
+            sys: SystemInfo {
+                name: "Linux".to_string(),
+                kernel_ver: "6.1".to_string(),
+                os_ver: "12".to_string(),
+                os_name: "Debian".to_string(),
+                host_name: "web1".to_string(),
+                load_avg: vec![0.0; 3],
+                uptime: 0,
+            },
+            mem: MemoryInfo {
+                total_mem: 0,
+                used_mem: 0,
+                total_swap: 0,
+                used_swap: 0,
+            },
+            cpu: CpuInfo {
+                count: 1,
+                avg_usage: 0.0,
+                usage: vec![0.0],
+            },
+            net: NetworkInfo { interfaces: vec![] },
+            disk: DisksInfo {
+                disks: vec![disk("/", 100), disk("/mnt/data", 100), disk("/proc", 0)],
+            },
+        };
+
+        let messages = discovery_messages("homeassistant", "web1", "simon/web1", &info);
+        let topics: Vec<&str> = messages.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/sensor/web1/cpu_usage/config",
+                "homeassistant/sensor/web1/memory_usage/config",
+                "homeassistant/sensor/web1/swap_usage/config",
+                "homeassistant/sensor/web1/load_1/config",
+                "homeassistant/sensor/web1/load_5/config",
+                "homeassistant/sensor/web1/load_15/config",
+                "homeassistant/sensor/web1/disk_root/config",
+                "homeassistant/sensor/web1/disk_mnt_data/config",
+                "homeassistant/sensor/web1/uptime/config",
+            ]
+        );
+
+        let config: serde_json::Value = serde_json::from_str(&messages[7].1).unwrap();
+        assert_eq!(config["unique_id"], "simon_web1_disk_mnt_data");
+        assert_eq!(config["state_topic"], "simon/web1/info");
+        assert_eq!(config["availability_topic"], "simon/web1/status");
+        assert_eq!(config["unit_of_measurement"], "%");
+        assert_eq!(config["device"]["identifiers"][0], "simon_web1");
+        assert_eq!(
+            config["value_template"],
+            "{% for d in value_json.disk.disks if d.mount_point == \"/mnt/data\" %}{{ (100 - 100 * d.free_space / d.total_space) | round(1) }}{% endfor %}"
+        );
+        let config: serde_json::Value = serde_json::from_str(&messages[3].1).unwrap();
+        assert_eq!(config["value_template"], "{{ value_json.sys.load_avg[0] }}");
+        assert!(config.get("unit_of_measurement").is_none());
+    }
+}
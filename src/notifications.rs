@@ -0,0 +1,1867 @@
+use crate::db::Database;
+use crate::hub::Hub;
+use crate::models::{
+    AlertDelivery, AlertmanagerNotif, DiscordNotif, EmailNotif, ExecNotif, MqttNotif,
+    NotificationConfig, NotificationMethod, NotificationTestResult, PagerDutyNotif,
+    QueuedNotification, SlackNotif, WebHookNotif,
+};
+use crate::mqtt;
+use lettre::message::{Mailbox, header::ContentType};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{debug, error, info, warn};
+use regex::{Captures, Regex};
+use reqwest::tls::Certificate;
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::{Duration, Instant};
+use sysinfo::System;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Values of an alert notification that templates can refer to as `{name}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlertContext {
+    pub alert_id: String,
+    pub alert_name: String,
+    pub metric: String,
+    pub resource: String, // Empty for system metrics
+    pub value: String,    // With its unit, empty without data
+    pub threshold: String,
+    pub host: String,
+    pub state: String,      // "firing" or "resolved"
+    pub transition: String, // "fired", "resolved", "renotified" or "escalated"
+    pub started_at: String, // RFC 3339, empty if the alert never fired
+    pub duration: String,   // How long the alert has been or was firing
+    pub message: String,    // `{notif_msg}`
+}
+
+/// Names of the variables of an alert context
+const VARIABLES: [&str; 12] = [
+    "alert_id",
+    "alert_name",
+    "metric",
+    "resource",
+    "value",
+    "threshold",
+    "host",
+    "state",
+    "transition",
+    "started_at",
+    "duration",
+    "notif_msg",
+];
+
+impl AlertContext {
+    fn variable(&self, name: &str) -> Option<&str> {
+        Some(match name {
+            "alert_id" => &self.alert_id,
+            "alert_name" => &self.alert_name,
+            "metric" => &self.metric,
+            "resource" => &self.resource,
+            "value" => &self.value,
+            "threshold" => &self.threshold,
+            "host" => &self.host,
+            "state" => &self.state,
+            "transition" => &self.transition,
+            "started_at" => &self.started_at,
+            "duration" => &self.duration,
+            "notif_msg" => &self.message,
+            _ => return None,
+        })
+    }
+
+    /// All the variables as a JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        VARIABLES
+            .iter()
+            .map(|name| {
+                let value = self.variable(name).unwrap_or_default();
+                (name.to_string(), value.into())
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+/// How values are escaped for the text they are substituted in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escape {
+    Plain,
+    Json,  // Inside a JSON string
+    Url,   // Percent-encoded, for URLs and form bodies
+    Topic, // MQTT topic, without level separators or wildcards
+}
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{([a-z_]+)\}").unwrap());
+
+/// Substitute the variables of a template in a single pass, so values can't inject placeholders.
+/// Unknown placeholders are left as they are, JSON bodies are full of braces.
+pub fn render(template: &str, context: &AlertContext, escape: Escape) -> String {
+    PLACEHOLDER
+        .replace_all(template, |caps: &Captures| {
+            match context.variable(&caps[1]) {
+                Some(value) => match escape {
+                    Escape::Plain => value.to_string(),
+                    Escape::Json => {
+                        let quoted = serde_json::Value::from(value).to_string();
+                        quoted[1..quoted.len() - 1].to_string()
+                    }
+                    Escape::Url => percent_encode(value),
+                    Escape::Topic => value.replace(['/', '+', '#', '\0'], "_"),
+                },
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Percent-encode everything but unreserved characters
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Escaping for the body of a webhook, from its content type or else its template
+fn body_escape(webhook: &WebHookNotif) -> Escape {
+    let content_type = webhook
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.to_ascii_lowercase());
+    match content_type {
+        Some(t) if t.contains("json") => Escape::Json,
+        Some(t) if t.contains("x-www-form-urlencoded") => Escape::Url,
+        Some(_) => Escape::Plain,
+        None => template_escape(&webhook.body),
+    }
+}
+
+/// Escaping for a template without a content type: JSON if it is valid JSON with its
+/// variables substituted, e.g. `{"text": "{notif_msg}"}` but not a bare `{notif_msg}`
+fn template_escape(template: &str) -> Escape {
+    let sample = PLACEHOLDER.replace_all(template, |caps: &Captures| {
+        if VARIABLES.contains(&&caps[1]) {
+            "x".to_string()
+        } else {
+            caps[0].to_string()
+        }
+    });
+    match serde_json::from_str::<serde_json::Value>(&sample) {
+        Ok(_) => Escape::Json,
+        Err(_) => Escape::Plain,
+    }
+}
+
+/// Outcome of an attempt at sending a notification, with what the other end answered
+#[derive(Debug)]
+pub struct Delivery {
+    pub result: Result<(), String>,
+    pub status: Option<u16>, // HTTP status of webhooks, SMTP reply code of emails
+    pub response: Option<String>, // Start of the response body, or of the program output
+}
+
+impl From<Result<(), String>> for Delivery {
+    fn from(result: Result<(), String>) -> Self {
+        Delivery {
+            result,
+            status: None,
+            response: None,
+        }
+    }
+}
+
+/// Characters of a response kept in the delivery log
+const SNIPPET_LEN: usize = 500;
+
+/// Start of a response for the delivery log, nothing if it's blank
+fn snippet(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| truncate(text, SNIPPET_LEN))
+}
+
+/// The first `len` characters of a text, followed by an ellipsis if it was longer
+fn truncate(text: &str, len: usize) -> String {
+    match text.char_indices().nth(len) {
+        Some((idx, _)) => format!("{}…", &text[..idx]),
+        None => text.to_string(),
+    }
+}
+
+/// Send a notification through the configured method, see `send_notifications`
+pub async fn send_notification(
+    method: &NotificationMethod,
+    alert_template: Option<&str>,
+    context: &AlertContext,
+) -> Delivery {
+    send_notifications(method, &[(alert_template, context)]).await
+}
+
+/// Send notifications through the configured method as one message, giving up after
+/// its timeout. Messages are rendered from the template of their alert, or else of the
+/// method, and replace the default one as `{notif_msg}`.
+pub async fn send_notifications(
+    method: &NotificationMethod,
+    notifications: &[(Option<&str>, &AlertContext)],
+) -> Delivery {
+    let contexts: Vec<AlertContext> = notifications
+        .iter()
+        .map(
+            |(alert_template, context)| match alert_template.or(method.template.as_deref()) {
+                Some(template) => AlertContext {
+                    message: render(template, context, Escape::Plain),
+                    ..(*context).clone()
+                },
+                None => (*context).clone(),
+            },
+        )
+        .collect();
+    let send = dispatch(method, &contexts);
+    match tokio::time::timeout(Duration::from_secs(method.timeout.into()), send).await {
+        Ok(delivery) => delivery,
+        Err(_) => Delivery::from(Err(format!("Timed out after {} s", method.timeout))),
+    }
+}
+
+/// Send the messages of one or more alerts. Several alerts are combined into one
+/// message, except by the kinds with a format that lists alerts one by one.
+async fn dispatch(method: &NotificationMethod, contexts: &[AlertContext]) -> Delivery {
+    let group;
+    let context = match contexts {
+        [context] => context,
+        _ => {
+            group = group_context(contexts);
+            &group
+        }
+    };
+    match &method.config {
+        NotificationConfig::WebHook(webhook) => send_webhook_notification(webhook, context).await,
+        NotificationConfig::Email(email) => {
+            send_email_notification(email, context, method.timeout).await
+        }
+        NotificationConfig::Exec(exec) => run_exec_notification(exec, context).await,
+        NotificationConfig::Mqtt(mqtt) => send_mqtt_notification(mqtt, context).await.into(),
+        NotificationConfig::Slack(slack) => {
+            post_json(&slack.url, &HashMap::new(), slack_payload(context)).await
+        }
+        NotificationConfig::Discord(discord) => {
+            post_json(&discord.url, &HashMap::new(), discord_payload(context)).await
+        }
+        NotificationConfig::Alertmanager(am) => {
+            let payload = alertmanager_payload(&method.name, contexts);
+            post_json(&am.url, &am.headers, payload).await
+        }
+        NotificationConfig::PagerDuty(pd) => {
+            // One event per alert, so each one keeps its own incident
+            let mut delivery = Delivery::from(Ok(()));
+            for context in contexts {
+                let payload = pagerduty_payload(pd, context);
+                delivery = post_json(&pd.url, &HashMap::new(), payload).await;
+                if delivery.result.is_err() {
+                    break;
+                }
+            }
+            delivery
+        }
+    }
+}
+
+/// Context of a message about several alerts: one line per message, firing if any of
+/// them is, and the distinct values of the other variables separated by commas
+fn group_context(contexts: &[AlertContext]) -> AlertContext {
+    let distinct = |variable: &str| {
+        let mut values: Vec<&str> = Vec::new();
+        for value in contexts.iter().filter_map(|c| c.variable(variable)) {
+            if !value.is_empty() && !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        values.join(", ")
+    };
+    let firing = contexts.iter().any(|c| c.state == "firing");
+    AlertContext {
+        alert_id: distinct("alert_id"),
+        alert_name: distinct("alert_name"),
+        metric: distinct("metric"),
+        resource: distinct("resource"),
+        value: distinct("value"),
+        threshold: distinct("threshold"),
+        host: distinct("host"),
+        state: if firing { "firing" } else { "resolved" }.to_string(),
+        transition: distinct("transition"),
+        started_at: contexts
+            .iter()
+            .map(|c| c.started_at.as_str())
+            .filter(|t| !t.is_empty())
+            .min()
+            .unwrap_or_default()
+            .to_string(),
+        duration: distinct("duration"),
+        message: contexts
+            .iter()
+            .map(|c| c.message.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Made-up firing alert for test notifications
+fn sample_context() -> AlertContext {
+    AlertContext {
+        alert_id: "test".to_string(),
+        alert_name: "Test notification".to_string(),
+        metric: "CPU Usage".to_string(),
+        resource: String::new(),
+        value: "97.5%".to_string(),
+        threshold: "90%".to_string(),
+        host: System::host_name().unwrap_or_default(),
+        state: "firing".to_string(),
+        transition: "fired".to_string(),
+        started_at: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        duration: "0 min".to_string(),
+        message: "This is a test notification from Simon".to_string(),
+    }
+}
+
+/// Send a sample notification through a method right away, bypassing the queue
+pub async fn send_test_notification(method: &NotificationMethod) -> NotificationTestResult {
+    let start = Instant::now();
+    let delivery = send_notification(method, None, &sample_context()).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    match &delivery.result {
+        Ok(()) => info!("Test notification sent through {}", method.name),
+        Err(e) => warn!("Test notification through {} failed: {}", method.name, e),
+    }
+    NotificationTestResult {
+        success: delivery.result.is_ok(),
+        error: delivery.result.err(),
+        status: delivery.status,
+        response: delivery.response,
+        latency_ms,
+    }
+}
+
+/// Delay before retrying a delivery that failed for the given time,
+/// doubling from 30 seconds up to an hour
+pub fn retry_delay(attempt: u32) -> i64 {
+    30i64
+        .saturating_mul(1 << attempt.saturating_sub(1).min(20))
+        .min(3600)
+}
+
+/// Deliver the queued notifications as they fall due, retrying failed ones with
+/// exponential backoff. Every attempt is recorded as a delivery of its alert event.
+pub async fn run_delivery_queue(db_path: &str, hub: Arc<Hub>) {
+    let db = match Database::new(db_path) {
+        Ok(db) => Arc::new(db),
+        Err(e) => {
+            error!("Failed to open database: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        let now = chrono::Utc::now().timestamp();
+        // Due notifications stay due after an error, so the next attempt is held back
+        let mut failed = false;
+        match db.due_notifications(now) {
+            Ok(due) if !due.is_empty() => {
+                let methods = match db.get_notification_methods() {
+                    Ok(methods) => methods,
+                    Err(e) => {
+                        error!("Failed to get notification methods: {}", e);
+                        failed = true;
+                        Vec::new()
+                    }
+                };
+                let due = if failed { Vec::new() } else { due };
+                let methods: Arc<Vec<NotificationMethod>> = Arc::new(methods);
+                // Notifications of a method in the same batch are sent as one message
+                let mut batches: Vec<Vec<QueuedNotification>> = Vec::new();
+                for notification in due {
+                    let batch = batches.iter_mut().find(|batch| {
+                        notification.batch.is_some()
+                            && batch[0].batch == notification.batch
+                            && batch[0].method_id == notification.method_id
+                    });
+                    match batch {
+                        Some(batch) => batch.push(notification),
+                        None => batches.push(vec![notification]),
+                    }
+                }
+                // Concurrently, so a slow receiver doesn't hold up the others
+                let mut deliveries = tokio::task::JoinSet::new();
+                for batch in batches {
+                    let (db, methods) = (db.clone(), methods.clone());
+                    deliveries.spawn(async move { deliver(&db, &methods, batch).await });
+                }
+                while let Some(result) = deliveries.join_next().await {
+                    if let Err(e) = result {
+                        error!("Notification delivery panicked: {}", e);
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("Failed to get queued notifications: {}", e);
+                failed = true;
+            }
+        }
+
+        let wait = match db.next_notification_time() {
+            Ok(Some(next)) => (next - chrono::Utc::now().timestamp()).clamp(0, 60),
+            Ok(None) => 60,
+            Err(e) => {
+                error!("Failed to get queued notifications: {}", e);
+                60
+            }
+        };
+        let wait = if failed { wait.max(1) } else { wait };
+        tokio::select! {
+            _ = hub.notifications.notified() => {}
+            _ = tokio::time::sleep(Duration::from_secs(wait as u64)) => {}
+        }
+    }
+}
+
+/// Attempt queued notifications of a method as one message, then drop them from the queue
+/// or schedule their retry
+async fn deliver(db: &Database, methods: &[NotificationMethod], batch: Vec<QueuedNotification>) {
+    let Some(first) = batch.first() else {
+        return;
+    };
+    // Deleting a method drops its queue, so a missing one is one whose config can't be read.
+    // Its notifications are held back until it's fixed, not picked up again right away.
+    let Some(method) = methods.iter().find(|m| m.id == first.method_id) else {
+        let attempt = first.attempts + 1;
+        let retry_at = chrono::Utc::now().timestamp() + retry_delay(attempt);
+        warn!(
+            "Notification method {} can't be read, retrying its notifications in {} s",
+            first.method_id,
+            retry_delay(attempt)
+        );
+        for notification in &batch {
+            if let Err(e) = db.reschedule_notification(notification.id, attempt, retry_at) {
+                error!("Failed to reschedule notification: {}", e);
+            }
+        }
+        return;
+    };
+    let dequeue = |notification: &QueuedNotification| {
+        if let Err(e) = db.dequeue_notification(notification.id) {
+            error!("Failed to remove queued notification: {}", e);
+        }
+    };
+    if !method.enabled {
+        debug!("Dropping notifications to disabled method {}", method.name);
+        batch.iter().for_each(dequeue);
+        return;
+    }
+    let mut notifications = Vec::new();
+    for notification in batch {
+        match serde_json::from_value::<AlertContext>(notification.context.clone()) {
+            Ok(context) => notifications.push((notification, context)),
+            Err(e) => {
+                error!("Dropping unreadable queued notification: {}", e);
+                dequeue(&notification);
+            }
+        }
+    }
+    if notifications.is_empty() {
+        return;
+    }
+
+    // Retried together, notifications of a batch have the same attempts
+    let attempt = notifications[0].0.attempts + 1;
+    let messages: Vec<_> = notifications
+        .iter()
+        .map(|(notification, context)| (notification.template.as_deref(), context))
+        .collect();
+    let delivery = send_notifications(method, &messages).await;
+    let now = chrono::Utc::now().timestamp();
+    let retry_at = match &delivery.result {
+        Ok(()) => {
+            notifications.iter().for_each(|(n, _)| dequeue(n));
+            None
+        }
+        Err(e) if attempt > method.max_retries => {
+            error!(
+                "Failed to send notification through {}, giving up after {} attempts: {}",
+                method.name, attempt, e
+            );
+            notifications.iter().for_each(|(n, _)| dequeue(n));
+            None
+        }
+        Err(e) => {
+            let retry_at = now + retry_delay(attempt);
+            warn!(
+                "Failed to send notification through {} (attempt {}), retrying in {} s: {}",
+                method.name,
+                attempt,
+                retry_at - now,
+                e
+            );
+            for (notification, _) in &notifications {
+                if let Err(e) = db.reschedule_notification(notification.id, attempt, retry_at) {
+                    error!("Failed to reschedule notification: {}", e);
+                }
+            }
+            Some(retry_at)
+        }
+    };
+
+    for event_id in notifications.iter().filter_map(|(n, _)| n.event_id) {
+        if let Err(e) = db.insert_alert_delivery(
+            event_id,
+            &AlertDelivery {
+                method_id: method.id.clone(),
+                method_name: method.name.clone(),
+                t: now,
+                success: delivery.result.is_ok(),
+                error: delivery.result.clone().err(),
+                attempt,
+                status: delivery.status,
+                response: delivery.response.clone(),
+                retry_at,
+            },
+        ) {
+            error!("Failed to record notification delivery: {}", e);
+        }
+    }
+}
+
+/// Check the settings of a notification method before it's saved
+pub fn validate_method(method: &NotificationMethod) -> Result<(), String> {
+    if !(1..=600).contains(&method.timeout) {
+        return Err("Timeout must be between 1 and 600 seconds".to_string());
+    }
+    if method.max_retries > 20 {
+        return Err("At most 20 retries are allowed".to_string());
+    }
+    if method
+        .digest_interval
+        .is_some_and(|interval| !(1..=1440).contains(&interval))
+    {
+        return Err("Digest interval must be between 1 and 1440 minutes".to_string());
+    }
+    match &method.config {
+        NotificationConfig::WebHook(_) => Ok(()),
+        NotificationConfig::Email(email) => {
+            if email.server.trim().is_empty() {
+                return Err("SMTP server is required".to_string());
+            }
+            if !EMAIL_SECURITY.contains(&email.security.as_str()) {
+                return Err(format!(
+                    "Unknown security \"{}\", expected one of {}",
+                    email.security,
+                    EMAIL_SECURITY.join(" ")
+                ));
+            }
+            // Catches invalid addresses
+            email_message(email, &AlertContext::default()).map(|_| ())
+        }
+        NotificationConfig::Exec(exec) => {
+            if exec.command.trim().is_empty() {
+                return Err("Command is required".to_string());
+            }
+            Ok(())
+        }
+        NotificationConfig::Mqtt(notif) => {
+            if notif.host.trim().is_empty() {
+                return Err("MQTT broker is required".to_string());
+            }
+            mqtt::qos(notif.qos)?;
+            // Values can't add wildcards, but the template itself could have some
+            let topic = render(&notif.topic, &AlertContext::default(), Escape::Topic);
+            if topic.is_empty() || topic.contains(['+', '#']) {
+                return Err(format!("Invalid MQTT topic \"{}\"", notif.topic));
+            }
+            Ok(())
+        }
+        NotificationConfig::Slack(SlackNotif { url })
+        | NotificationConfig::Discord(DiscordNotif { url })
+        | NotificationConfig::Alertmanager(AlertmanagerNotif { url, .. }) => validate_url(url),
+        NotificationConfig::PagerDuty(pd) => {
+            if pd.routing_key.trim().is_empty() {
+                return Err("PagerDuty routing key is required".to_string());
+            }
+            if !PAGERDUTY_SEVERITIES.contains(&pd.severity.as_str()) {
+                return Err(format!(
+                    "Unknown severity \"{}\", expected one of {}",
+                    pd.severity,
+                    PAGERDUTY_SEVERITIES.join(" ")
+                ));
+            }
+            validate_url(&pd.url)
+        }
+    }
+}
+
+/// Check that a URL is an absolute HTTP(S) one
+fn validate_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if ["http", "https"].contains(&parsed.scheme()) => Ok(()),
+        Ok(_) => Err(format!("URL \"{}\" must be http or https", url)),
+        Err(e) => Err(format!("Invalid URL \"{}\": {}", url, e)),
+    }
+}
+
+/// Severities of PagerDuty events
+const PAGERDUTY_SEVERITIES: [&str; 4] = ["critical", "error", "warning", "info"];
+
+/// Connection security of SMTP servers: STARTTLS, implicit TLS or none
+const EMAIL_SECURITY: [&str; 3] = ["starttls", "tls", "none"];
+
+const DEFAULT_EMAIL_SUBJECT: &str = "[{host}] {alert_name} is {state}";
+
+/// Email of a notification, with its subject and body rendered from their templates
+fn email_message(email: &EmailNotif, context: &AlertContext) -> Result<Message, String> {
+    let subject = match email.subject.trim() {
+        "" => DEFAULT_EMAIL_SUBJECT,
+        subject => subject,
+    };
+    let body = match email.body.trim() {
+        "" => "{notif_msg}",
+        _ => &email.body,
+    };
+    let from: Mailbox = email
+        .from
+        .parse()
+        .map_err(|e| format!("Invalid sender address \"{}\": {}", email.from, e))?;
+    if email.to.is_empty() {
+        return Err("Email needs at least one recipient".to_string());
+    }
+    let mut builder = Message::builder()
+        .from(from)
+        .subject(render(subject, context, Escape::Plain))
+        .header(ContentType::TEXT_PLAIN);
+    for to in &email.to {
+        builder = builder.to(to
+            .parse()
+            .map_err(|e| format!("Invalid recipient address \"{}\": {}", to, e))?);
+    }
+    builder
+        .body(render(body, context, Escape::Plain))
+        .map_err(|e| format!("Failed to build email: {}", e))
+}
+
+/// Send an email notification through an SMTP server, with the timeout of its method
+async fn send_email_notification(
+    email: &EmailNotif,
+    context: &AlertContext,
+    timeout: u32,
+) -> Delivery {
+    let message = match email_message(email, context) {
+        Ok(message) => message,
+        Err(e) => return Err(e).into(),
+    };
+    let builder = match email.security.as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&email.server),
+        "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+            &email.server,
+        )),
+        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&email.server),
+    }
+    .map_err(|e| format!("Invalid SMTP server {}: {}", email.server, e));
+    let builder = match builder {
+        Ok(builder) => builder,
+        Err(e) => return Err(e).into(),
+    };
+    let mut builder = builder
+        .port(email.port)
+        .timeout(Some(Duration::from_secs(timeout.into())));
+    if !email.username.is_empty() {
+        builder = builder.credentials(Credentials::new(
+            email.username.clone(),
+            email.password.clone(),
+        ));
+    }
+
+    match builder.build().send(message).await {
+        Ok(response) => {
+            info!("Email notification sent successfully");
+            Delivery {
+                result: Ok(()),
+                status: Some(response.code().into()),
+                response: snippet(&response.message().collect::<Vec<_>>().join("\n")),
+            }
+        }
+        Err(e) => Delivery {
+            result: Err(format!("Failed to send email: {}", e)),
+            status: e.status().map(u16::from),
+            response: None,
+        },
+    }
+}
+
+/// Run a program for a notification. The variables are passed as `SIMON_<NAME>`
+/// environment variables and as a JSON object on stdin. It's killed once the timeout
+/// of its method drops the delivery.
+async fn run_exec_notification(exec: &ExecNotif, context: &AlertContext) -> Delivery {
+    let mut command = Command::new(&exec.command);
+    command
+        .args(
+            exec.args
+                .iter()
+                .map(|arg| render(arg, context, Escape::Plain)),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    for name in VARIABLES {
+        let value = context.variable(name).unwrap_or_default();
+        command.env(format!("SIMON_{}", name.to_uppercase()), value);
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return Err(format!("Failed to run {}: {}", exec.command, e)).into(),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // Programs don't have to read it
+        if let Err(e) = stdin
+            .write_all(format!("{}\n", context.to_json()).as_bytes())
+            .await
+        {
+            debug!("Failed to write to stdin of {}: {}", exec.command, e);
+        }
+    }
+    let output = match child.wait_with_output().await {
+        Ok(output) => output,
+        Err(e) => return Err(format!("Failed to wait for {}: {}", exec.command, e)).into(),
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() {
+        info!(
+            "Exec notification {} succeeded: {}",
+            exec.command,
+            stdout.trim()
+        );
+        Delivery {
+            result: Ok(()),
+            status: None,
+            response: snippet(&stdout),
+        }
+    } else {
+        warn!(
+            "Exec notification {} failed with {}, stdout: {}, stderr: {}",
+            exec.command,
+            output.status,
+            stdout.trim(),
+            stderr.trim()
+        );
+        Delivery {
+            result: Err(format!(
+                "{} failed with {}: {}",
+                exec.command,
+                output.status,
+                stderr.trim()
+            )),
+            status: None,
+            response: snippet(&stdout),
+        }
+    }
+}
+
+/// Publish a notification to an MQTT broker, the payload defaulting to the variables as JSON
+async fn send_mqtt_notification(notif: &MqttNotif, context: &AlertContext) -> Result<(), String> {
+    let topic = render(&notif.topic, context, Escape::Topic);
+    let payload = match notif.payload.trim() {
+        "" => context.to_json().to_string(),
+        template => render(template, context, template_escape(template)),
+    };
+    mqtt::publish(notif, &topic, payload).await?;
+    info!("MQTT notification published to {}", topic);
+    Ok(())
+}
+
+/// Build an HTTP client, loading CA certificates manually if the default
+/// platform verifier fails (e.g. in scratch Docker containers).
+fn build_http_client() -> Result<Client, String> {
+    // Try the default builder first (uses rustls-platform-verifier)
+    match Client::builder().build() {
+        Ok(client) => return Ok(client),
+        Err(e) => {
+            debug!(
+                "Default HTTP client builder failed ({}), trying manual CA cert loading",
+                e
+            );
+        }
+    }
+
+    // Fallback: load CA certs manually and use tls_certs_only() to bypass
+    // rustls-platform-verifier, which can fail in minimal containers (e.g. FROM scratch)
+    let cert_paths = [
+        "/etc/ssl/certs/ca-certificates.crt",
+        "/etc/pki/tls/certs/ca-bundle.crt",
+        "/etc/ssl/ca-bundle.pem",
+    ];
+
+    for path in &cert_paths {
+        if let Ok(pem_data) = std::fs::read(path) {
+            let certs = Certificate::from_pem_bundle(&pem_data)
+                .map_err(|e| format!("Failed to parse CA certificates from {}: {}", path, e))?;
+            if !certs.is_empty() {
+                debug!("Loaded {} CA certificates from {}", certs.len(), path);
+                return Client::builder()
+                    .tls_certs_only(certs)
+                    .build()
+                    .map_err(|e| format!("Failed to build HTTP client with manual certs: {}", e));
+            }
+        }
+    }
+
+    Err(
+        "Failed to build HTTP client: platform verifier unavailable and no CA certificates found"
+            .to_string(),
+    )
+}
+
+/// Shared by all webhooks, so connections are reused
+static HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
+
+fn http_client() -> Result<&'static Client, String> {
+    if let Some(client) = HTTP_CLIENT.get() {
+        return Ok(client);
+    }
+    let client = build_http_client()?;
+    Ok(HTTP_CLIENT.get_or_init(|| client))
+}
+
+fn header_map(headers: &HashMap<String, String>) -> Result<reqwest::header::HeaderMap, String> {
+    let mut map = reqwest::header::HeaderMap::new();
+    for (key, value) in headers {
+        map.insert(
+            reqwest::header::HeaderName::from_bytes(key.as_bytes())
+                .map_err(|e| format!("Invalid header name: {}", e))?,
+            reqwest::header::HeaderValue::from_str(value)
+                .map_err(|e| format!("Invalid header value: {}", e))?,
+        );
+    }
+    Ok(map)
+}
+
+/// Request of a webhook notification, with its URL and body rendered
+fn webhook_request(
+    webhook: &WebHookNotif,
+    context: &AlertContext,
+) -> Result<RequestBuilder, String> {
+    let client = http_client()?;
+
+    let url = render(&webhook.url, context, Escape::Url);
+    let body = render(&webhook.body, context, body_escape(webhook));
+    let headers = header_map(&webhook.headers)?;
+
+    // Build request based on method
+    let request_builder = match webhook.method.to_uppercase().as_str() {
+        "GET" => client.get(&url),
+        "POST" => client.post(&url).body(body),
+        "PUT" => client.put(&url).body(body),
+        "PATCH" => client.patch(&url).body(body),
+        "DELETE" => client.delete(&url),
+        _ => return Err(format!("Unsupported HTTP method: {}", webhook.method)),
+    };
+
+    Ok(request_builder.headers(headers))
+}
+
+/// Send a webhook notification
+async fn send_webhook_notification(webhook: &WebHookNotif, context: &AlertContext) -> Delivery {
+    match webhook_request(webhook, context) {
+        Ok(request) => http_delivery(request).await,
+        Err(e) => Err(e).into(),
+    }
+}
+
+/// Send the request of a notification, keeping what the other end answered
+async fn http_delivery(request: RequestBuilder) -> Delivery {
+    let mut response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return Err(format!("Failed to send webhook request: {:?}", e)).into(),
+    };
+
+    // Only the start of the body is read, for the delivery log
+    let status = response.status();
+    let mut body = Vec::new();
+    while body.len() < SNIPPET_LEN * 4
+        && let Ok(Some(chunk)) = response.chunk().await
+    {
+        body.extend_from_slice(&chunk);
+    }
+    let result = if status.is_success() {
+        info!("Webhook notification sent successfully");
+        Ok(())
+    } else {
+        Err(format!("Webhook request failed with status: {}", status))
+    };
+    Delivery {
+        result,
+        status: Some(status.as_u16()),
+        response: snippet(&String::from_utf8_lossy(&body)),
+    }
+}
+
+/// POST a JSON payload built by Simon, for services with a format of their own
+async fn post_json(
+    url: &str,
+    headers: &HashMap<String, String>,
+    payload: serde_json::Value,
+) -> Delivery {
+    let request = http_client().and_then(|client| {
+        Ok(client
+            .post(url)
+            .headers(header_map(headers)?)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload.to_string()))
+    });
+    match request {
+        Ok(request) => http_delivery(request).await,
+        Err(e) => Err(e).into(),
+    }
+}
+
+/// Value, threshold, host and duration of an alert, the ones it has
+fn summary_fields(context: &AlertContext) -> Vec<(&'static str, &str)> {
+    [
+        ("Value", &context.value),
+        ("Threshold", &context.threshold),
+        ("Host", &context.host),
+        ("Duration", &context.duration),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .map(|(name, value)| (name, value.as_str()))
+    .collect()
+}
+
+/// Title of chat messages, e.g. "High CPU is firing"
+fn title(context: &AlertContext) -> String {
+    format!("{} is {}", context.alert_name, context.state)
+}
+
+/// Slack incoming webhook message, as an attachment that Mattermost also understands
+fn slack_payload(context: &AlertContext) -> serde_json::Value {
+    let fields: Vec<_> = summary_fields(context)
+        .into_iter()
+        .map(|(name, value)| json!({ "title": name, "value": value, "short": true }))
+        .collect();
+    json!({
+        "text": context.message,
+        "attachments": [{
+            "fallback": context.message,
+            "color": if context.state == "resolved" { "#2eb67d" } else { "#e01e5a" },
+            "title": title(context),
+            "fields": fields,
+            "footer": "Simon",
+            "ts": chrono::Utc::now().timestamp(),
+        }],
+    })
+}
+
+/// Discord webhook message with an embed, within Discord's length limits
+fn discord_payload(context: &AlertContext) -> serde_json::Value {
+    let fields: Vec<_> = summary_fields(context)
+        .into_iter()
+        .map(
+            |(name, value)| json!({ "name": name, "value": truncate(value, 1023), "inline": true }),
+        )
+        .collect();
+    let mut embed = json!({
+        "title": truncate(&title(context), 255),
+        "description": truncate(&context.message, 4095),
+        "color": if context.state == "resolved" { 0x2eb67d } else { 0xe01e5a },
+        "fields": fields,
+        "footer": { "text": "Simon" },
+    });
+    if !context.started_at.is_empty() {
+        embed["timestamp"] = context.started_at.clone().into();
+    }
+    json!({ "embeds": [embed] })
+}
+
+/// Alertmanager webhook receiver payload, a group of the alerts
+fn alertmanager_payload(receiver: &str, contexts: &[AlertContext]) -> serde_json::Value {
+    let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let alerts: Vec<_> = contexts
+        .iter()
+        .map(|context| alertmanager_alert(context, &now))
+        .collect();
+
+    // Labels and annotations all the alerts share
+    let common = |key: &str| {
+        let mut common = alerts
+            .first()
+            .and_then(|alert| alert[key].as_object().cloned())
+            .unwrap_or_default();
+        for alert in alerts.iter().skip(1) {
+            common.retain(|name, value| alert[key].get(name) == Some(value));
+        }
+        common
+    };
+    let common_labels = common("labels");
+    let mut group_labels = common_labels.clone();
+    group_labels.retain(|name, _| name == "alert_id" || name == "instance");
+    let group_key = group_labels
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(",");
+    let firing = contexts.iter().any(|c| c.state == "firing");
+    json!({
+        "version": "4",
+        "groupKey": format!("{{}}:{{{}}}", group_key),
+        "truncatedAlerts": 0,
+        "status": if firing { "firing" } else { "resolved" },
+        "receiver": receiver,
+        "groupLabels": group_labels,
+        "commonLabels": common_labels,
+        "commonAnnotations": common("annotations"),
+        "externalURL": "",
+        "alerts": alerts,
+    })
+}
+
+fn alertmanager_alert(context: &AlertContext, now: &str) -> serde_json::Value {
+    let mut labels = serde_json::Map::new();
+    labels.insert("alertname".to_string(), context.alert_name.clone().into());
+    labels.insert("alert_id".to_string(), context.alert_id.clone().into());
+    labels.insert("instance".to_string(), context.host.clone().into());
+    labels.insert("metric".to_string(), context.metric.clone().into());
+    if !context.resource.is_empty() {
+        labels.insert("resource".to_string(), context.resource.clone().into());
+    }
+    let starts_at = match context.started_at.as_str() {
+        "" => now,
+        started_at => started_at,
+    };
+    // Alertmanager's zero time for alerts that haven't ended
+    let ends_at = match context.state.as_str() {
+        "resolved" => now,
+        _ => "0001-01-01T00:00:00Z",
+    };
+    json!({
+        "status": context.state,
+        "labels": labels,
+        "annotations": {
+            "summary": context.message,
+            "value": context.value,
+            "threshold": context.threshold,
+        },
+        "startsAt": starts_at,
+        "endsAt": ends_at,
+        "generatorURL": "",
+        "fingerprint": format!("{:016x}", fnv1a(&dedup_key(context))),
+    })
+}
+
+/// Identifies the alert across its notifications, so they end up in the same incident
+fn dedup_key(context: &AlertContext) -> String {
+    format!("simon/{}/{}", context.host, context.alert_id)
+}
+
+/// 64-bit FNV-1a hash, stable across builds unlike the standard library's hasher
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// PagerDuty event triggering the incident of the alert, or resolving it
+fn pagerduty_payload(pd: &PagerDutyNotif, context: &AlertContext) -> serde_json::Value {
+    if context.state == "resolved" {
+        return json!({
+            "routing_key": pd.routing_key,
+            "event_action": "resolve",
+            "dedup_key": dedup_key(context),
+        });
+    }
+
+    let mut payload = json!({
+        "summary": truncate(&context.message, 1023),
+        "source": if context.host.is_empty() { "simon" } else { &context.host },
+        "severity": pd.severity,
+        "class": context.transition,
+        "custom_details": context.to_json(),
+    });
+    for (field, value) in [
+        ("timestamp", &context.started_at),
+        ("component", &context.metric),
+        ("group", &context.resource),
+    ] {
+        if !value.is_empty() {
+            payload[field] = value.clone().into();
+        }
+    }
+    json!({
+        "routing_key": pd.routing_key,
+        "event_action": "trigger",
+        "dedup_key": dedup_key(context),
+        "payload": payload,
+        "client": "Simon",
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::{AlertEvent, DeliveryLogQuery};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn context() -> AlertContext {
+        AlertContext {
+            alert_name: "Disk \"data\" full".to_string(),
+            value: "95%".to_string(),
+            host: "{alert_name}".to_string(),
+            message: "ALERT: a & b\nc".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Method of a kind with the config of its fixture below, named after the kind
+    pub(crate) fn method(kind: &str) -> NotificationMethod {
+        let config = match kind {
+            "webhook" => NotificationConfig::WebHook(webhook("http://127.0.0.1/".to_string())),
+            "email" => NotificationConfig::Email(email(25)),
+            "exec" => NotificationConfig::Exec(exec("true")),
+            "mqtt" => NotificationConfig::Mqtt(mqtt(1883, "simon/{alert_id}", "")),
+            "slack" => NotificationConfig::Slack(SlackNotif {
+                url: "https://hooks.slack.com/services/T/B/X".to_string(),
+            }),
+            "pagerduty" => NotificationConfig::PagerDuty(pagerduty(
+                "https://events.pagerduty.com/v2/enqueue".to_string(),
+            )),
+            _ => unimplemented!("no fixture for {}", kind),
+        };
+        NotificationMethod {
+            id: kind.to_string(),
+            name: kind.to_string(),
+            kind: kind.to_string(),
+            enabled: true,
+            config,
+            template: None,
+            timeout: 5,
+            max_retries: 1,
+            grouping: false,
+            digest_interval: None,
+        }
+    }
+
+    fn event(alert_id: &str, firing: bool) -> AlertEvent {
+        AlertEvent {
+            id: 0,
+            alert_id: alert_id.to_string(),
+            t: 0,
+            transition: if firing { "fired" } else { "resolved" }.to_string(),
+            value: None,
+            threshold: 90.0,
+            message: if firing { "ALERT" } else { "RESOLVED" }.to_string(),
+            silenced_by: None,
+            deliveries: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_escaping() {
+        let ctx = context();
+        assert_eq!(
+            render("{alert_name} at {value}", &ctx, Escape::Plain),
+            "Disk \"data\" full at 95%"
+        );
+        assert_eq!(
+            render(
+                r#"{"text": "{alert_name}: {notif_msg}", "n": {unknown}}"#,
+                &ctx,
+                Escape::Json
+            ),
+            r#"{"text": "Disk \"data\" full: ALERT: a & b\nc", "n": {unknown}}"#
+        );
+        assert_eq!(
+            render(
+                "https://x/send?text={notif_msg}&v={value}",
+                &ctx,
+                Escape::Url
+            ),
+            "https://x/send?text=ALERT%3A%20a%20%26%20b%0Ac&v=95%25"
+        );
+        // Substituted values aren't rendered again
+        assert_eq!(render("{host}", &ctx, Escape::Plain), "{alert_name}");
+    }
+
+    #[test]
+    fn test_body_escape() {
+        let mut webhook = WebHookNotif {
+            url: String::new(),
+            method: "POST".to_string(),
+            headers: HashMap::new(),
+            body: " {\"message\": \"{notif_msg}\"}".to_string(),
+        };
+        assert_eq!(body_escape(&webhook), Escape::Json);
+        webhook.body = "message={notif_msg}".to_string();
+        assert_eq!(body_escape(&webhook), Escape::Plain);
+        // A bare variable isn't JSON even if it starts with a brace
+        webhook.body = "{notif_msg}".to_string();
+        assert_eq!(body_escape(&webhook), Escape::Plain);
+        assert_eq!(
+            render(&webhook.body, &context(), body_escape(&webhook)),
+            "ALERT: a & b\nc"
+        );
+        webhook.body = "[{\"text\": \"{notif_msg}\", \"value\": \"{value}\"}]".to_string();
+        assert_eq!(body_escape(&webhook), Escape::Json);
+        webhook.headers.insert(
+            "Content-Type".to_string(),
+            "application/x-www-form-urlencoded".to_string(),
+        );
+        assert_eq!(body_escape(&webhook), Escape::Url);
+    }
+
+    fn email(port: u16) -> EmailNotif {
+        EmailNotif {
+            server: "127.0.0.1".to_string(),
+            port,
+            security: "none".to_string(),
+            username: "simon".to_string(),
+            password: "secret".to_string(),
+            from: "Simon <simon@example.com>".to_string(),
+            to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+            subject: String::new(),
+            body: "{notif_msg}\nValue: {value}".to_string(),
+        }
+    }
+
+    /// Accept a single SMTP session, returning the lines the client sent
+    async fn fake_smtp_server(listener: TcpListener) -> Vec<String> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        let mut received = Vec::new();
+        let mut in_data = false;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            received.push(line.clone());
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                b"250 Queued\r\n"
+            } else if line.starts_with("EHLO") {
+                b"250-localhost\r\n250 AUTH PLAIN LOGIN\r\n"
+            } else if line.starts_with("AUTH") {
+                b"235 Authenticated\r\n"
+            } else if line == "DATA" {
+                in_data = true;
+                b"354 Go ahead\r\n"
+            } else if line == "QUIT" {
+                b"221 Bye\r\n"
+            } else {
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_send_email() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_smtp_server(listener));
+
+        let context = AlertContext {
+            alert_name: "CPU Usage".to_string(),
+            host: "nas".to_string(),
+            state: "firing".to_string(),
+            ..context()
+        };
+        let delivery = send_email_notification(&email(port), &context, 5).await;
+        delivery.result.unwrap();
+        assert_eq!(delivery.status, Some(250));
+        assert_eq!(delivery.response.as_deref(), Some("Queued"));
+        let received = server.await.unwrap();
+
+        assert!(received.iter().any(|l| l.starts_with("AUTH")));
+        assert!(received.contains(&"MAIL FROM:<simon@example.com>".to_string()));
+        assert!(received.contains(&"RCPT TO:<a@example.com>".to_string()));
+        assert!(received.contains(&"RCPT TO:<b@example.com>".to_string()));
+        assert!(received.contains(&"Subject: [nas] CPU Usage is firing".to_string()));
+        assert!(received.contains(&"Value: 95%".to_string()));
+    }
+
+    #[test]
+    fn test_validate_email() {
+        let with = |email| NotificationMethod {
+            config: NotificationConfig::Email(email),
+            ..method("email")
+        };
+        assert!(validate_method(&method("email")).is_ok());
+        let mut invalid = email(25);
+        invalid.to.push("not an address".to_string());
+        assert!(validate_method(&with(invalid)).is_err());
+        let mut invalid = email(25);
+        invalid.to.clear();
+        assert!(validate_method(&with(invalid)).is_err());
+        let mut invalid = email(25);
+        invalid.security = "ssl".to_string();
+        assert!(validate_method(&with(invalid)).is_err());
+    }
+
+    fn exec(script: &str) -> ExecNotif {
+        ExecNotif {
+            command: "/bin/sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                script.to_string(),
+                "{alert_name}".to_string(),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exec_notification() {
+        let context = AlertContext {
+            alert_name: "CPU Usage".to_string(),
+            state: "firing".to_string(),
+            ..context()
+        };
+        // Arguments after the script are $0, $1, ...
+        let script = r#"read -r json
+            test "$0" = "CPU Usage" &&
+            test "$SIMON_STATE" = firing &&
+            test "$SIMON_NOTIF_MSG" = "$(printf 'ALERT: a & b\nc')" &&
+            echo "$json" | grep -q '"alert_name":"CPU Usage"'"#;
+        run_exec_notification(&exec(script), &context)
+            .await
+            .result
+            .unwrap();
+
+        let delivery =
+            run_exec_notification(&exec("echo partial; echo oops >&2; exit 3"), &context).await;
+        let error = delivery.result.unwrap_err();
+        assert!(error.contains("oops"), "{}", error);
+        assert_eq!(delivery.response.as_deref(), Some("partial"));
+        let slow = NotificationMethod {
+            config: NotificationConfig::Exec(exec("sleep 5")),
+            timeout: 1,
+            ..method("exec")
+        };
+        let error = send_notification(&slow, None, &context)
+            .await
+            .result
+            .unwrap_err();
+        assert_eq!(error, "Timed out after 1 s");
+    }
+
+    /// Accept a single MQTT session, returning the topic, payload and flags of the published message
+    async fn fake_mqtt_broker(listener: TcpListener) -> (String, String, u8) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut published = None;
+        while let Ok(header) = stream.read_u8().await {
+            let (mut length, mut shift) = (0usize, 0);
+            loop {
+                let byte = stream.read_u8().await.unwrap();
+                length |= ((byte & 0x7f) as usize) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await.unwrap();
+            match header >> 4 {
+                1 => stream.write_all(&[0x20, 2, 0, 0]).await.unwrap(), // CONNECT
+                3 => {
+                    let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    let topic = String::from_utf8(body[2..2 + topic_len].to_vec()).unwrap();
+                    let mut payload = &body[2 + topic_len..];
+                    if header & 0x06 != 0 {
+                        stream
+                            .write_all(&[0x40, 2, payload[0], payload[1]])
+                            .await
+                            .unwrap();
+                        payload = &payload[2..];
+                    }
+                    let payload = String::from_utf8(payload.to_vec()).unwrap();
+                    published = Some((topic, payload, header & 0x0f));
+                }
+                14 => break, // DISCONNECT
+                _ => {}
+            }
+        }
+        published.unwrap()
+    }
+
+    fn mqtt(port: u16, topic: &str, payload: &str) -> MqttNotif {
+        MqttNotif {
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: false,
+            username: "simon".to_string(),
+            password: "secret".to_string(),
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            qos: 1,
+            retain: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_mqtt() {
+        let context = AlertContext {
+            resource: "/mnt/data".to_string(),
+            state: "firing".to_string(),
+            ..context()
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_mqtt_broker(listener));
+        send_mqtt_notification(&mqtt(port, "simon/{resource}/{state}", ""), &context)
+            .await
+            .unwrap();
+        let (topic, payload, flags) = server.await.unwrap();
+        assert_eq!(topic, "simon/_mnt_data/firing");
+        // QoS 1 and retain
+        assert_eq!(flags, 0x03);
+        let json: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(json["alert_name"], "Disk \"data\" full");
+        assert_eq!(json["notif_msg"], "ALERT: a & b\nc");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_mqtt_broker(listener));
+        let template = r#"{"name": "{alert_name}"}"#;
+        send_mqtt_notification(&mqtt(port, "simon/alerts", template), &context)
+            .await
+            .unwrap();
+        let (_, payload, _) = server.await.unwrap();
+        assert_eq!(payload, r#"{"name": "Disk \"data\" full"}"#);
+    }
+
+    #[test]
+    fn test_validate_mqtt() {
+        let with = |notif| NotificationMethod {
+            config: NotificationConfig::Mqtt(notif),
+            ..method("mqtt")
+        };
+        assert!(validate_method(&method("mqtt")).is_ok());
+        assert!(validate_method(&with(mqtt(1883, "simon/#", ""))).is_err());
+        assert!(validate_method(&with(mqtt(1883, "", ""))).is_err());
+        let mut invalid = mqtt(1883, "simon", "");
+        invalid.qos = 3;
+        assert!(validate_method(&with(invalid)).is_err());
+    }
+
+    fn pagerduty(url: String) -> PagerDutyNotif {
+        PagerDutyNotif {
+            routing_key: "R0UT1NGK3Y".to_string(),
+            severity: "warning".to_string(),
+            url,
+        }
+    }
+
+    #[test]
+    fn test_payload_formats() {
+        let firing = AlertContext {
+            alert_id: "a1".to_string(),
+            alert_name: "High CPU".to_string(),
+            metric: "CPU Usage".to_string(),
+            value: "97%".to_string(),
+            threshold: "90%".to_string(),
+            host: "vm".to_string(),
+            state: "firing".to_string(),
+            transition: "fired".to_string(),
+            started_at: "2026-01-01T10:00:00+00:00".to_string(),
+            message: "x".repeat(5000),
+            ..Default::default()
+        };
+        let resolved = AlertContext {
+            state: "resolved".to_string(),
+            transition: "resolved".to_string(),
+            ..firing.clone()
+        };
+
+        let slack = slack_payload(&firing);
+        assert_eq!(slack["attachments"][0]["title"], "High CPU is firing");
+        assert_eq!(slack["attachments"][0]["fields"][0]["value"], "97%");
+        // Empty variables aren't shown
+        assert_eq!(
+            slack["attachments"][0]["fields"].as_array().unwrap().len(),
+            3
+        );
+
+        let discord = discord_payload(&firing);
+        let description = discord["embeds"][0]["description"].as_str().unwrap();
+        assert_eq!(description.chars().count(), 4096);
+        assert_eq!(discord["embeds"][0]["timestamp"], firing.started_at);
+
+        let am = alertmanager_payload("oncall", std::slice::from_ref(&firing));
+        assert_eq!(am["status"], "firing");
+        assert_eq!(am["alerts"][0]["labels"]["alertname"], "High CPU");
+        assert_eq!(am["alerts"][0]["endsAt"], "0001-01-01T00:00:00Z");
+        assert!(am["alerts"][0]["labels"].get("resource").is_none());
+        let am_resolved = alertmanager_payload("oncall", std::slice::from_ref(&resolved));
+        assert_eq!(am_resolved["status"], "resolved");
+        assert_ne!(am_resolved["alerts"][0]["endsAt"], "0001-01-01T00:00:00Z");
+        assert_eq!(
+            am["alerts"][0]["fingerprint"],
+            am_resolved["alerts"][0]["fingerprint"]
+        );
+
+        // Resolving closes the incident opened with the same key
+        let pd = pagerduty(String::new());
+        let trigger = pagerduty_payload(&pd, &firing);
+        let resolve = pagerduty_payload(&pd, &resolved);
+        assert_eq!(trigger["event_action"], "trigger");
+        assert_eq!(trigger["payload"]["severity"], "warning");
+        assert_eq!(trigger["payload"]["source"], "vm");
+        assert!(trigger["payload"].get("group").is_none());
+        assert_eq!(resolve["event_action"], "resolve");
+        assert_eq!(resolve["dedup_key"], trigger["dedup_key"]);
+        assert!(resolve.get("payload").is_none());
+    }
+
+    #[test]
+    fn test_validate_payload_kinds() {
+        let with = |config| NotificationMethod {
+            config,
+            ..method("slack")
+        };
+        let slack = |url: &str| {
+            with(NotificationConfig::Slack(SlackNotif {
+                url: url.to_string(),
+            }))
+        };
+        assert!(validate_method(&method("slack")).is_ok());
+        assert!(validate_method(&slack("")).is_err());
+        assert!(validate_method(&slack("ftp://example.com")).is_err());
+
+        assert!(validate_method(&method("pagerduty")).is_ok());
+        let mut invalid = pagerduty("https://events.pagerduty.com/v2/enqueue".to_string());
+        invalid.severity = "high".to_string();
+        assert!(validate_method(&with(NotificationConfig::PagerDuty(invalid))).is_err());
+        let mut invalid = pagerduty("https://events.pagerduty.com/v2/enqueue".to_string());
+        invalid.routing_key.clear();
+        assert!(validate_method(&with(NotificationConfig::PagerDuty(invalid))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_pagerduty() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 8192];
+            let n = stream.read(&mut request).await.unwrap();
+            let body = br#"{"status":"success","message":"Event processed"}"#;
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 202 Accepted\r\nContent-Length: {}\r\n\r\n",
+                        body.len()
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+            stream.write_all(body).await.unwrap();
+            String::from_utf8_lossy(&request[..n]).to_string()
+        });
+
+        let pd = NotificationMethod {
+            config: NotificationConfig::PagerDuty(pagerduty(format!(
+                "http://127.0.0.1:{}/v2/enqueue",
+                port
+            ))),
+            template: Some("{alert_name} on {host}".to_string()),
+            ..method("pagerduty")
+        };
+        let context = AlertContext {
+            state: "firing".to_string(),
+            ..context()
+        };
+        let delivery = send_notification(&pd, None, &context).await;
+        let request = server.await.unwrap();
+        assert!(delivery.result.is_ok());
+        assert_eq!(delivery.status, Some(202));
+        assert!(request.starts_with("POST /v2/enqueue "));
+        assert!(request.contains("content-type: application/json"));
+        let body: serde_json::Value =
+            serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["event_action"], "trigger");
+        assert_eq!(
+            body["payload"]["summary"],
+            "Disk \"data\" full on {alert_name}"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay_and_snippet() {
+        let delays: Vec<i64> = (1..=9).map(retry_delay).collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(retry_delay(u32::MAX), 3600);
+
+        assert_eq!(snippet(" \n "), None);
+        assert_eq!(snippet(" ok\n").as_deref(), Some("ok"));
+        let long = snippet(&"é".repeat(SNIPPET_LEN + 1)).unwrap();
+        assert_eq!(long.chars().count(), SNIPPET_LEN + 1);
+        assert!(long.ends_with('…'));
+    }
+
+    fn webhook(url: String) -> WebHookNotif {
+        WebHookNotif {
+            url,
+            method: "POST".to_string(),
+            headers: HashMap::new(),
+            body: "{notif_msg}".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let _ = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 10\r\n\r\n try later",
+                )
+                .await
+                .unwrap();
+        });
+
+        let hook = NotificationMethod {
+            config: NotificationConfig::WebHook(webhook(format!("http://127.0.0.1:{}/", port))),
+            ..method("webhook")
+        };
+        let delivery = send_notification(&hook, None, &context()).await;
+        server.await.unwrap();
+        assert!(delivery.result.unwrap_err().contains("503"));
+        assert_eq!(delivery.status, Some(503));
+        assert_eq!(delivery.response.as_deref(), Some("try later"));
+    }
+
+    #[tokio::test]
+    async fn test_send_test_notification() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).to_string()
+        });
+
+        let hook = NotificationMethod {
+            config: NotificationConfig::WebHook(webhook(format!("http://127.0.0.1:{}/", port))),
+            ..method("webhook")
+        };
+        let result = send_test_notification(&hook).await;
+        let request = server.await.unwrap();
+        assert!(request.contains("This is a test notification"));
+        assert!(result.success);
+        assert_eq!(result.error, None);
+        assert_eq!(result.status, Some(200));
+        assert_eq!(result.response.as_deref(), Some("ok"));
+    }
+
+    #[test]
+    fn test_group_context() {
+        let cpu = AlertContext {
+            alert_id: "cpu".to_string(),
+            alert_name: "High CPU".to_string(),
+            threshold: "90%".to_string(),
+            host: "vm".to_string(),
+            state: "resolved".to_string(),
+            started_at: "2026-01-01T10:05:00+00:00".to_string(),
+            message: "CPU is back to normal".to_string(),
+            ..Default::default()
+        };
+        let memory = AlertContext {
+            alert_id: "mem".to_string(),
+            alert_name: "Low memory".to_string(),
+            state: "firing".to_string(),
+            started_at: "2026-01-01T10:00:00+00:00".to_string(),
+            message: "Memory is low".to_string(),
+            ..cpu.clone()
+        };
+        let group = group_context(&[cpu, memory]);
+        assert_eq!(group.alert_id, "cpu, mem");
+        assert_eq!(group.alert_name, "High CPU, Low memory");
+        assert_eq!(group.threshold, "90%");
+        assert_eq!(group.host, "vm");
+        assert_eq!(group.state, "firing");
+        assert_eq!(group.started_at, "2026-01-01T10:00:00+00:00");
+        assert_eq!(group.message, "CPU is back to normal\nMemory is low");
+    }
+
+    #[tokio::test]
+    async fn test_batch_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).to_string()
+        });
+
+        let db = Database::new(":memory:").unwrap();
+        let hook = NotificationMethod {
+            config: NotificationConfig::WebHook(webhook(format!("http://127.0.0.1:{}/", port))),
+            grouping: true,
+            ..method("webhook")
+        };
+        db.save_notification_method(&hook).unwrap();
+        let mut queue = Vec::new();
+        for (alert_id, template) in [("cpu", None), ("mem", Some("{alert_id} is {state}"))] {
+            let event_id = db.insert_alert_event(&event(alert_id, true)).unwrap();
+            let context = AlertContext {
+                alert_id: alert_id.to_string(),
+                state: "firing".to_string(),
+                message: format!("{} is high", alert_id),
+                ..Default::default()
+            };
+            queue.push(QueuedNotification {
+                id: 0,
+                event_id: Some(event_id),
+                method_id: "webhook".to_string(),
+                template: template.map(str::to_string),
+                context: serde_json::to_value(context).unwrap(),
+                attempts: 0,
+                next_attempt: 0,
+                batch: Some("check:1".to_string()),
+            });
+        }
+        db.enqueue_notifications(&queue).unwrap();
+
+        let methods = db.get_notification_methods().unwrap();
+        let batch = db.due_notifications(0).unwrap();
+        assert_eq!(batch.len(), 2);
+        deliver(&db, &methods, batch).await;
+        // One message for both, each alert's own template applied
+        let request = server.await.unwrap();
+        assert!(request.ends_with("\r\n\r\ncpu is high\nmem is firing"));
+        assert_eq!(db.next_notification_time().unwrap(), None);
+
+        let query = DeliveryLogQuery {
+            start_time: None,
+            end_time: None,
+            method_id: Some("webhook".to_string()),
+            alert_id: None,
+            success: Some(true),
+            limit: None,
+        };
+        let mut alerts: Vec<String> = db
+            .query_deliveries(&query)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.alert_id)
+            .collect();
+        alerts.sort();
+        assert_eq!(alerts, ["cpu", "mem"]);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_retries() {
+        let db = Database::new(":memory:").unwrap();
+        // Nothing listens on the port once the listener is dropped
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let hook = NotificationMethod {
+            config: NotificationConfig::WebHook(webhook(format!("http://127.0.0.1:{}/", port))),
+            ..method("webhook")
+        };
+        db.save_notification_method(&hook).unwrap();
+        let event_id = db.insert_alert_event(&event("a", true)).unwrap();
+        db.enqueue_notifications(&[QueuedNotification {
+            id: 0,
+            event_id: Some(event_id),
+            method_id: "webhook".to_string(),
+            template: None,
+            context: serde_json::to_value(context()).unwrap(),
+            attempts: 0,
+            next_attempt: 0,
+            batch: None,
+        }])
+        .unwrap();
+
+        let methods = db.get_notification_methods().unwrap();
+        let queued = db.due_notifications(0).unwrap().pop().unwrap();
+        let before = chrono::Utc::now().timestamp();
+        deliver(&db, &methods, vec![queued]).await;
+        // Rescheduled with backoff, then given up after the single retry
+        assert!(db.due_notifications(before).unwrap().is_empty());
+        assert!(db.next_notification_time().unwrap().unwrap() >= before + 30);
+        let queued = db.due_notifications(i64::MAX).unwrap().pop().unwrap();
+        assert_eq!(queued.attempts, 1);
+        deliver(&db, &methods, vec![queued]).await;
+        assert_eq!(db.next_notification_time().unwrap(), None);
+
+        let query = DeliveryLogQuery {
+            start_time: None,
+            end_time: None,
+            method_id: Some("webhook".to_string()),
+            alert_id: None,
+            success: Some(false),
+            limit: None,
+        };
+        let log = db.query_deliveries(&query).unwrap();
+        let attempts: Vec<(u32, bool)> = log
+            .iter()
+            .map(|entry| (entry.delivery.attempt, entry.delivery.retry_at.is_some()))
+            .collect();
+        assert_eq!(attempts, [(2, false), (1, true)]);
+        assert_eq!(log[0].alert_id, "a");
+        assert!(log[0].delivery.error.is_some());
+
+        // Held back, not left due, when the method can't be read
+        db.enqueue_notifications(&[QueuedNotification {
+            id: 0,
+            event_id: None,
+            method_id: "webhook".to_string(),
+            template: None,
+            context: serde_json::to_value(context()).unwrap(),
+            attempts: 0,
+            next_attempt: 0,
+            batch: None,
+        }])
+        .unwrap();
+        let queued = db.due_notifications(0).unwrap();
+        let before = chrono::Utc::now().timestamp();
+        deliver(&db, &[], queued).await;
+        assert!(db.due_notifications(before).unwrap().is_empty());
+        let queued = db.due_notifications(i64::MAX).unwrap().pop().unwrap();
+        assert_eq!(queued.attempts, 1);
+        assert!(queued.next_attempt >= before + 30);
+    }
+}
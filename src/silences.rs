@@ -0,0 +1,170 @@
+use crate::alerts;
+use crate::models::{Alert, Silence, SilenceRecurrence};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, TimeZone, Weekday};
+
+/// Check that a silence can be evaluated before it's saved
+pub fn validate_silence(silence: &Silence) -> Result<(), String> {
+    match (silence.end_time, &silence.recurrence) {
+        (Some(end_time), _) if end_time <= silence.start_time => {
+            return Err("End time must be after the start time".to_string());
+        }
+        (None, None) => return Err("Silences need an end time unless recurring".to_string()),
+        _ => {}
+    }
+    if let Some(recurrence) = &silence.recurrence {
+        if recurrence.days.is_empty() {
+            return Err("Recurring silences need at least one day".to_string());
+        }
+        for day in &recurrence.days {
+            day.parse::<Weekday>()
+                .map_err(|_| format!("Unknown day \"{}\", expected mon to sun", day))?;
+        }
+        NaiveTime::parse_from_str(&recurrence.start, "%H:%M")
+            .map_err(|_| format!("Invalid start \"{}\", expected HH:MM", recurrence.start))?;
+        if !(1..=7 * 24 * 60).contains(&recurrence.duration) {
+            return Err("Duration must be between 1 minute and a week".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// First silence muting the notifications of an alert at time `t`
+pub fn find_active<'a>(silences: &'a [Silence], alert: &Alert, t: i64) -> Option<&'a Silence> {
+    silences
+        .iter()
+        .find(|silence| is_active(silence, t) && matches(silence, alert))
+}
+
+fn is_active(silence: &Silence, t: i64) -> bool {
+    if t < silence.start_time || silence.end_time.is_some_and(|end_time| t >= end_time) {
+        return false;
+    }
+    match (&silence.recurrence, DateTime::from_timestamp(t, 0)) {
+        (Some(recurrence), Some(t)) => in_weekly_window(recurrence, &t.with_timezone(&Local)),
+        (Some(_), None) => false,
+        (None, _) => true,
+    }
+}
+
+/// Whether `t` falls in one of the weekly windows, which may span several days
+fn in_weekly_window<Tz: TimeZone>(recurrence: &SilenceRecurrence, t: &DateTime<Tz>) -> bool {
+    let Ok(start) = NaiveTime::parse_from_str(&recurrence.start, "%H:%M") else {
+        return false;
+    };
+    let days: Vec<Weekday> = recurrence
+        .days
+        .iter()
+        .filter_map(|day| day.parse().ok())
+        .collect();
+    let date = t.date_naive();
+    (0..=7).any(|days_back| {
+        let day = date - Duration::days(days_back);
+        // Windows starting in a DST gap are skipped that day
+        days.contains(&day.weekday())
+            && t.timezone()
+                .from_local_datetime(&day.and_time(start))
+                .earliest()
+                .is_some_and(|window| {
+                    window <= *t && *t < window + Duration::minutes(recurrence.duration)
+                })
+    })
+}
+
+/// Whether a silence applies to an alert. For composite alerts,
+/// the category and resource match if any of the variables does.
+fn matches(silence: &Silence, alert: &Alert) -> bool {
+    if !silence.alert_ids.is_empty() && !silence.alert_ids.contains(&alert.id) {
+        return false;
+    }
+    if silence.cat.is_none() && silence.resrc.is_none() {
+        return true;
+    }
+    alerts::alert_vars(alert).iter().any(|(var, _)| {
+        silence.cat.as_ref().is_none_or(|cat| *cat == var.cat)
+            && silence
+                .resrc
+                .as_ref()
+                .is_none_or(|resrc| *resrc == var.resrc)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AlertVar;
+    use chrono::{FixedOffset, NaiveDate};
+
+    fn silence(start_time: i64, end_time: Option<i64>) -> Silence {
+        Silence {
+            id: "s".to_string(),
+            comment: String::new(),
+            alert_ids: Vec::new(),
+            cat: None,
+            resrc: None,
+            start_time,
+            end_time,
+            recurrence: None,
+        }
+    }
+
+    #[test]
+    fn test_weekly_window() {
+        // Saturday 23:00 for 3 hours, in UTC+2
+        let recurrence = SilenceRecurrence {
+            days: vec!["sat".to_string()],
+            start: "23:00".to_string(),
+            duration: 180,
+        };
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let at = |day: u32, hour: u32, min: u32| {
+            let local = NaiveDate::from_ymd_opt(2026, 10, day)
+                .unwrap()
+                .and_hms_opt(hour, min, 0)
+                .unwrap();
+            tz.from_local_datetime(&local).unwrap()
+        };
+        // 2026-10-17 is a Saturday
+        assert!(!in_weekly_window(&recurrence, &at(17, 22, 59)));
+        assert!(in_weekly_window(&recurrence, &at(17, 23, 0)));
+        assert!(in_weekly_window(&recurrence, &at(18, 1, 59)));
+        assert!(!in_weekly_window(&recurrence, &at(18, 2, 0)));
+        assert!(!in_weekly_window(&recurrence, &at(16, 23, 30)));
+        assert!(in_weekly_window(&recurrence, &at(24, 23, 30)));
+    }
+
+    #[test]
+    fn test_silence_bounds_and_matching() {
+        let mut alert: Alert = serde_json::from_value(serde_json::json!({
+            "id": "a1",
+            "var": {"cat": "disk", "var": "disk_usage", "resrc": "/"},
+            "threshold": 90.0,
+            "operator": ">",
+            "time_window": 5,
+            "enabled": true,
+            "firing": false,
+            "notif_methods": []
+        }))
+        .unwrap();
+
+        let mut s = silence(100, Some(200));
+        assert!(validate_silence(&s).is_ok());
+        assert!(find_active(std::slice::from_ref(&s), &alert, 99).is_none());
+        assert!(find_active(std::slice::from_ref(&s), &alert, 100).is_some());
+        assert!(find_active(std::slice::from_ref(&s), &alert, 200).is_none());
+
+        s.cat = Some("disk".to_string());
+        s.resrc = Some("/data".to_string());
+        assert!(!matches(&s, &alert));
+        alert.var = AlertVar {
+            cat: "disk".to_string(),
+            var: "disk_usage".to_string(),
+            resrc: "/data".to_string(),
+        };
+        assert!(matches(&s, &alert));
+        s.alert_ids = vec!["a2".to_string()];
+        assert!(!matches(&s, &alert));
+
+        assert!(validate_silence(&silence(100, None)).is_err());
+        assert!(validate_silence(&silence(100, Some(100))).is_err());
+    }
+}
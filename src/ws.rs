@@ -0,0 +1,571 @@
+use crate::alerts;
+use crate::config::Config;
+use crate::db::Database;
+use crate::hub::{self, Hub, Snapshot, SnapshotReceiver};
+use crate::models::{AlertStateChange, DockerInfo, GeneralInfo, ProcessesInfo, WsClientMessage};
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Extension, State, WebSocketUpgrade};
+use axum::response::IntoResponse;
+use log::{debug, error, warn};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use sysinfo::System;
+use tokio::sync::broadcast;
+use tokio::time::{Duration, Instant};
+
+/// Longest interval a client may ask for, in seconds
+const MAX_TOPIC_INTERVAL: u64 = 3600;
+
+/// Frame sent to clients of the multiplexed websocket
+#[derive(Serialize)]
+struct ServerFrame<'a, T: Serialize> {
+    topic: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+    kind: &'a str, // "full", "delta", "event" or "error"
+    data: T,
+}
+
+/// Changes of a keyed collection since the last frame sent to a client. New items are sent
+/// whole, changed ones as merge patches (RFC 7386) that still carry their key field.
+#[derive(Serialize)]
+struct KeyedDelta<K> {
+    t: i64,
+    changed: Vec<Value>,
+    removed: Vec<K>,
+}
+
+/// Per-client state of a subscribed topic
+struct Subscription<S> {
+    interval: Duration,
+    last_sent: Option<Instant>,
+    state: Option<S>, // What the client currently has, deltas are computed against it
+}
+
+impl<S> Subscription<S> {
+    fn new(interval: Duration) -> Self {
+        Subscription {
+            interval,
+            last_sent: None,
+            state: None,
+        }
+    }
+
+    fn is_due(&self) -> bool {
+        self.last_sent
+            .is_none_or(|last_sent| last_sent.elapsed() >= self.interval)
+    }
+}
+
+/// State of a single multiplexed websocket connection
+struct Connection {
+    update_interval: u64,
+    db: Arc<Database>,
+    general: Option<Subscription<Value>>,
+    processes: Option<Subscription<HashMap<u32, Value>>>,
+    docker: Option<Subscription<HashMap<String, Value>>>,
+    containers: HashMap<String, Subscription<Value>>,
+    alerts: bool,
+    general_rx: Option<SnapshotReceiver<GeneralInfo>>,
+    processes_rx: Option<SnapshotReceiver<ProcessesInfo>>,
+    docker_rx: Option<SnapshotReceiver<Option<DockerInfo>>>,
+    alerts_rx: Option<broadcast::Receiver<AlertStateChange>>,
+}
+
+/// Multiplexed websocket.
+/// Clients send `{"action": "subscribe", "topic": ..., "interval": ...}` and
+/// `{"action": "unsubscribe", "topic": ...}` text messages for the topics
+/// "general", "processes", "docker", "container" (with an `id`) and "alerts".
+/// The server answers with gzipped JSON frames, a full snapshot first and deltas afterwards.
+/// Alert subscribers get a full snapshot again when they fall behind on alert changes.
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State((_, config)): State<(Arc<Mutex<System>>, Arc<Config>)>,
+    Extension(hub): Extension<Arc<Hub>>,
+    Extension(db): Extension<Arc<Database>>,
+) -> impl IntoResponse {
+    debug!("Multiplexed websocket connection requested");
+    ws.on_upgrade(move |socket| {
+        handle_socket(socket, hub, Connection::new(config.update_interval, db))
+    })
+}
+
+async fn handle_socket(mut socket: WebSocket, hub: Arc<Hub>, mut conn: Connection) {
+    debug!("Multiplexed websocket connection established");
+
+    loop {
+        let frames = tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => conn.handle_message(&text, &hub),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => Vec::new(),
+            },
+            snapshot = next_snapshot(&mut conn.general_rx) => conn.on_general(snapshot),
+            snapshot = next_snapshot(&mut conn.processes_rx) => conn.on_processes(snapshot),
+            snapshot = next_snapshot(&mut conn.docker_rx) => conn.on_docker(snapshot),
+            change = next_alert_change(&mut conn.alerts_rx) => conn.on_alert_change(change),
+        };
+
+        for frame in frames {
+            if socket.send(Message::Binary(frame.into())).await.is_err() {
+                debug!("Multiplexed websocket connection closed");
+                return;
+            }
+        }
+    }
+    debug!("Multiplexed websocket connection closed");
+}
+
+/// Wait for the next snapshot of a topic, or forever if not subscribed
+async fn next_snapshot<T>(rx: &mut Option<SnapshotReceiver<T>>) -> Option<Arc<Snapshot<T>>> {
+    match rx {
+        Some(rx) => match rx.changed().await {
+            Ok(()) => rx.borrow_and_update().clone(),
+            Err(_) => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the next alert state change, or the number of changes missed by falling behind
+async fn next_alert_change(
+    rx: &mut Option<broadcast::Receiver<AlertStateChange>>,
+) -> Result<AlertStateChange, u64> {
+    match rx {
+        Some(rx) => match rx.recv().await {
+            Ok(change) => Ok(change),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => Err(skipped),
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// Serialize and gzip a frame
+fn encode<T: Serialize>(topic: &str, id: Option<&str>, kind: &str, data: T) -> Vec<u8> {
+    let frame = ServerFrame {
+        topic,
+        id,
+        kind,
+        data,
+    };
+    hub::gzip(serde_json::to_string(&frame).unwrap().as_bytes())
+}
+
+impl Connection {
+    fn new(update_interval: u64, db: Arc<Database>) -> Self {
+        Connection {
+            update_interval,
+            db,
+            general: None,
+            processes: None,
+            docker: None,
+            containers: HashMap::new(),
+            alerts: false,
+            general_rx: None,
+            processes_rx: None,
+            docker_rx: None,
+            alerts_rx: None,
+        }
+    }
+
+    fn handle_message(&mut self, text: &str, hub: &Hub) -> Vec<Vec<u8>> {
+        let msg = match serde_json::from_str::<WsClientMessage>(text) {
+            Ok(msg) => msg,
+            Err(e) => {
+                debug!("Invalid websocket message: {}", e);
+                return vec![encode("", None, "error", format!("Invalid message: {}", e))];
+            }
+        };
+        debug!("Websocket message: {:?}", msg);
+
+        match msg {
+            WsClientMessage::Subscribe {
+                topic,
+                id,
+                interval,
+            } => {
+                let interval = Duration::from_secs(
+                    interval
+                        .unwrap_or(self.update_interval)
+                        .clamp(self.update_interval, MAX_TOPIC_INTERVAL),
+                );
+                match (topic.as_str(), id) {
+                    ("general", _) => {
+                        self.general = Some(Subscription::new(interval));
+                        let rx = hub.general.subscribe();
+                        let snapshot = rx.borrow().clone();
+                        self.general_rx = Some(rx);
+                        return self.on_general(snapshot);
+                    }
+                    ("processes", _) => {
+                        self.processes = Some(Subscription::new(interval));
+                        let rx = hub.processes.subscribe();
+                        let snapshot = rx.borrow().clone();
+                        self.processes_rx = Some(rx);
+                        return self.on_processes(snapshot);
+                    }
+                    ("docker", _) => {
+                        self.docker = Some(Subscription::new(interval));
+                        return self.subscribe_docker(hub);
+                    }
+                    ("container", Some(id)) => {
+                        self.containers.insert(id, Subscription::new(interval));
+                        return self.subscribe_docker(hub);
+                    }
+                    ("alerts", _) => {
+                        self.alerts = true;
+                        self.alerts_rx = Some(hub.alerts.subscribe());
+                        return self.alerts_snapshot().into_iter().collect();
+                    }
+                    ("container", None) => {
+                        return vec![encode("container", None, "error", "Missing container id")];
+                    }
+                    (topic, _) => {
+                        return vec![encode(
+                            topic,
+                            None,
+                            "error",
+                            format!("Unknown topic: {}", topic),
+                        )];
+                    }
+                }
+            }
+            WsClientMessage::Unsubscribe { topic, id } => match (topic.as_str(), id) {
+                ("general", _) => {
+                    self.general = None;
+                    self.general_rx = None;
+                }
+                ("processes", _) => {
+                    self.processes = None;
+                    self.processes_rx = None;
+                }
+                ("docker", _) => self.docker = None,
+                ("container", Some(id)) => {
+                    self.containers.remove(&id);
+                }
+                ("container", None) => self.containers.clear(),
+                ("alerts", _) => {
+                    self.alerts = false;
+                    self.alerts_rx = None;
+                }
+                _ => {}
+            },
+        }
+
+        // Stop listening to docker once neither docker nor any container is subscribed
+        if self.docker.is_none() && self.containers.is_empty() {
+            self.docker_rx = None;
+        }
+        Vec::new()
+    }
+
+    fn subscribe_docker(&mut self, hub: &Hub) -> Vec<Vec<u8>> {
+        let rx = self
+            .docker_rx
+            .take()
+            .unwrap_or_else(|| hub.docker.subscribe());
+        let snapshot = rx.borrow().clone();
+        self.docker_rx = Some(rx);
+        self.on_docker(snapshot)
+    }
+
+    /// Current state of all alerts, sent when subscribing to the alerts topic
+    fn alerts_snapshot(&self) -> Option<Vec<u8>> {
+        match alerts::get_alerts(&self.db) {
+            Ok(alerts) => Some(encode("alerts", None, "full", alerts)),
+            Err(e) => {
+                error!("Failed to get alerts: {}", e);
+                None
+            }
+        }
+    }
+
+    fn on_general(&mut self, snapshot: Option<Arc<Snapshot<GeneralInfo>>>) -> Vec<Vec<u8>> {
+        let (Some(sub), Some(snapshot)) = (self.general.as_mut(), snapshot) else {
+            return Vec::new();
+        };
+        if !sub.is_due() {
+            return Vec::new();
+        }
+
+        let value = serde_json::to_value(&snapshot.data).unwrap();
+        sub.last_sent = Some(Instant::now());
+        let frame = match sub.state.as_ref() {
+            Some(previous) => match merge_patch(previous, &value) {
+                Some(patch) => encode("general", None, "delta", patch),
+                None => return Vec::new(),
+            },
+            None => encode("general", None, "full", &value),
+        };
+        sub.state = Some(value);
+        vec![frame]
+    }
+
+    fn on_processes(&mut self, snapshot: Option<Arc<Snapshot<ProcessesInfo>>>) -> Vec<Vec<u8>> {
+        let (Some(sub), Some(snapshot)) = (self.processes.as_mut(), snapshot) else {
+            return Vec::new();
+        };
+        if !sub.is_due() {
+            return Vec::new();
+        }
+
+        sub.last_sent = Some(Instant::now());
+        let info = &snapshot.data;
+        let (delta, state) = keyed_delta(
+            info.t,
+            sub.state.as_ref(),
+            &info.processes,
+            |p| p.pid,
+            "pid",
+        );
+        let frame = match sub.state {
+            Some(_) => encode("processes", None, "delta", delta),
+            None => encode("processes", None, "full", info),
+        };
+        sub.state = Some(state);
+        vec![frame]
+    }
+
+    fn on_docker(&mut self, snapshot: Option<Arc<Snapshot<Option<DockerInfo>>>>) -> Vec<Vec<u8>> {
+        let Some(snapshot) = snapshot else {
+            return Vec::new();
+        };
+        let mut frames = Vec::new();
+
+        if let Some(sub) = self.docker.as_mut()
+            && sub.is_due()
+        {
+            sub.last_sent = Some(Instant::now());
+            match &snapshot.data {
+                Some(info) => {
+                    let (delta, state) = keyed_delta(
+                        info.t,
+                        sub.state.as_ref(),
+                        &info.containers,
+                        |c| c.id.clone(),
+                        "id",
+                    );
+                    frames.push(match sub.state {
+                        Some(_) => encode("docker", None, "delta", delta),
+                        None => encode("docker", None, "full", info),
+                    });
+                    sub.state = Some(state);
+                }
+                None => {
+                    // Docker is inaccessible, start over with a full snapshot once it's back
+                    frames.push(encode("docker", None, "full", Value::Null));
+                    sub.state = None;
+                }
+            }
+        }
+
+        for (id, sub) in self.containers.iter_mut() {
+            if !sub.is_due() {
+                continue;
+            }
+            sub.last_sent = Some(Instant::now());
+
+            let container = snapshot.data.as_ref().and_then(|info| {
+                info.containers.iter().find(|c| {
+                    c.id == *id
+                        || c.id.starts_with(id.as_str())
+                        || c.name.trim_start_matches('/') == id
+                })
+            });
+            let value = serde_json::to_value(container).unwrap();
+            match sub.state.as_ref() {
+                Some(previous) if !previous.is_null() && !value.is_null() => {
+                    if let Some(patch) = merge_patch(previous, &value) {
+                        frames.push(encode("container", Some(id), "delta", patch));
+                    }
+                }
+                Some(previous) if *previous == value => {}
+                _ => frames.push(encode("container", Some(id), "full", &value)),
+            }
+            sub.state = Some(value);
+        }
+
+        frames
+    }
+
+    fn on_alert_change(&mut self, change: Result<AlertStateChange, u64>) -> Vec<Vec<u8>> {
+        match change {
+            Ok(change) if self.alerts => vec![encode("alerts", None, "event", change)],
+            // The client can't tell which changes it missed, so it starts over
+            Err(skipped) if self.alerts => {
+                warn!("Websocket client missed {} alert state changes", skipped);
+                self.alerts_snapshot().into_iter().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Items that were added or changed since the previous state, and keys that disappeared,
+/// along with the state the client has once it applies them
+fn keyed_delta<T: Serialize, K: Eq + Hash + Clone>(
+    t: i64,
+    previous: Option<&HashMap<K, Value>>,
+    current: &[T],
+    key: impl Fn(&T) -> K,
+    key_field: &str,
+) -> (KeyedDelta<K>, HashMap<K, Value>) {
+    let mut changed = Vec::new();
+    let mut state = HashMap::with_capacity(current.len());
+    for item in current {
+        let k = key(item);
+        let value = serde_json::to_value(item).unwrap();
+        match previous.and_then(|previous| previous.get(&k)) {
+            Some(prev) => {
+                if let Some(mut patch) = merge_patch(prev, &value) {
+                    patch[key_field] = value[key_field].clone();
+                    changed.push(patch);
+                }
+            }
+            None => changed.push(value.clone()),
+        }
+        state.insert(k, value);
+    }
+    let removed = previous
+        .into_iter()
+        .flat_map(|previous| previous.keys())
+        .filter(|k| !state.contains_key(*k))
+        .cloned()
+        .collect();
+
+    (
+        KeyedDelta {
+            t,
+            changed,
+            removed,
+        },
+        state,
+    )
+}
+
+/// JSON merge patch (RFC 7386) turning `previous` into `current`, None if they are equal
+fn merge_patch(previous: &Value, current: &Value) -> Option<Value> {
+    if previous == current {
+        return None;
+    }
+    match (previous, current) {
+        (Value::Object(prev), Value::Object(cur)) => {
+            let mut patch = Map::new();
+            for (key, value) in cur {
+                match prev.get(key) {
+                    Some(prev_value) => {
+                        if let Some(p) = merge_patch(prev_value, value) {
+                            patch.insert(key.clone(), p);
+                        }
+                    }
+                    None => {
+                        patch.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            for key in prev.keys() {
+                if !cur.contains_key(key) {
+                    patch.insert(key.clone(), Value::Null);
+                }
+            }
+            Some(Value::Object(patch))
+        }
+        _ => Some(current.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ProcessInfo;
+    use serde_json::json;
+    use std::io::Read;
+
+    #[test]
+    fn test_alerts_resync_after_lag() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let mut conn = Connection::new(1, db);
+        let hub = Hub::new();
+        let subscribe = r#"{"action": "subscribe", "topic": "alerts"}"#;
+        assert_eq!(conn.handle_message(subscribe, &hub).len(), 1);
+
+        let frames = conn.on_alert_change(Err(3));
+        assert_eq!(frames.len(), 1);
+        let mut frame = String::new();
+        flate2::read::GzDecoder::new(frames[0].as_slice())
+            .read_to_string(&mut frame)
+            .unwrap();
+        let frame: Value = serde_json::from_str(&frame).unwrap();
+        assert_eq!(
+            (&frame["topic"], &frame["kind"]),
+            (&json!("alerts"), &json!("full"))
+        );
+        assert_eq!(frame["data"], json!([]));
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let previous = json!({"t": 1, "mem": {"used": 10, "total": 20}, "gone": true});
+        let current = json!({"t": 2, "mem": {"used": 12, "total": 20}, "cpu": [1, 2]});
+        assert_eq!(
+            merge_patch(&previous, &current),
+            Some(json!({"t": 2, "mem": {"used": 12}, "cpu": [1, 2], "gone": null}))
+        );
+        assert_eq!(merge_patch(&current, &current), None);
+    }
+
+    fn process(pid: u32, runtime: u64, name: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            runtime,
+            name: name.to_string(),
+            mem: 4096,
+            cpu: 0.5,
+            stat: "S".to_string(),
+            cmd: format!("/usr/bin/{} --daemon", name),
+            env: "PATH=/usr/bin".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_keyed_delta() {
+        let previous = [
+            process(1, 10, "a"),
+            process(2, 10, "b"),
+            process(3, 10, "c"),
+        ];
+        let (_, state) = keyed_delta(0, None, &previous, |p| p.pid, "pid");
+        let current = [
+            process(1, 10, "a"),
+            process(2, 10, "B"),
+            process(4, 10, "d"),
+        ];
+        let (delta, state) = keyed_delta(1, Some(&state), &current, |p| p.pid, "pid");
+        assert_eq!(
+            delta.changed,
+            vec![
+                json!({"pid": 2, "name": "B", "cmd": "/usr/bin/B --daemon"}),
+                serde_json::to_value(&current[2]).unwrap()
+            ]
+        );
+        assert_eq!(delta.removed, vec![3]);
+        assert_eq!(state.len(), 3);
+    }
+
+    #[test]
+    fn test_keyed_delta_runtime_only() {
+        let previous: Vec<_> = (1..=100).map(|pid| process(pid, 10, "worker")).collect();
+        let (_, state) = keyed_delta(0, None, &previous, |p| p.pid, "pid");
+        let current: Vec<_> = (1..=100).map(|pid| process(pid, 12, "worker")).collect();
+        let (delta, _) = keyed_delta(2, Some(&state), &current, |p| p.pid, "pid");
+        assert_eq!(delta.changed[0], json!({"pid": 1, "runtime": 12}));
+        // Only the runtime of each process is sent, not the whole of it
+        let size = serde_json::to_vec(&delta).unwrap().len();
+        assert!(size < 2600, "{}", size);
+        assert!(size * 5 < serde_json::to_vec(&current).unwrap().len());
+    }
+}
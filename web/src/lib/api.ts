@@ -7,6 +7,8 @@ import type {
 	Alert,
 	AlertVar,
 	NotificationMethod,
+	NotificationTestResult,
+	Silence,
 	DirectoryListing
 } from './types';
 
@@ -61,6 +63,7 @@ export async function getSystemCapabilities() {
 export async function getHistoricalData(options: HistoricalQueryOptions) {
 	const params = new URLSearchParams();
 	params.append('resolution', options.resolution);
+	if (options.agg) params.append('agg', options.agg);
 	if (options.start_time) params.append('start_time', options.start_time.toString());
 	if (options.end_time) params.append('end_time', options.end_time.toString());
 	if (options.limit) params.append('limit', options.limit.toString());
@@ -97,6 +100,35 @@ export async function deleteAlert(id: string) {
 	});
 }
 
+/**
+ * Get all silences
+ */
+export async function getSilences() {
+	return apiFetch<Silence[]>('api/silences');
+}
+
+/**
+ * Create or update a silence
+ */
+export async function saveSilence(silence: Silence) {
+	return apiFetch<Silence[]>('api/silences', {
+		method: 'POST',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(silence)
+	});
+}
+
+/**
+ * Delete a silence
+ */
+export async function deleteSilence(id: string) {
+	return apiFetch<Silence[]>(`api/silences/${id}`, {
+		method: 'DELETE'
+	});
+}
+
 /**
  * Get available alert variables
  */
@@ -124,6 +156,19 @@ export async function saveNotificationMethod(method: NotificationMethod) {
 	});
 }
 
+/**
+ * Send a test notification through a method, saved or not
+ */
+export async function testNotificationMethod(method: NotificationMethod) {
+	return apiFetch<NotificationTestResult>('api/notif_methods/test', {
+		method: 'POST',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(method)
+	});
+}
+
 /**
  * Delete a notification method
  */
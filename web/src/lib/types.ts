@@ -18,6 +18,7 @@ export interface SystemCapabilities {
 
 export interface HistoricalQueryOptions {
 	resolution: 'second' | 'minute' | 'hour' | 'day';
+	agg?: 'avg' | 'min' | 'max' | 'p95';
 	start_time?: number;
 	end_time?: number;
 	limit?: number;
@@ -117,6 +118,8 @@ export interface DockerContainer {
 	status: string;
 	state: string;
 	created: number;
+	restart_count: number;
+	health: string; // Empty without a healthcheck
 	ports: DockerPort[];
 	cpu_usage: number;
 	mem_usage: number;
@@ -135,14 +138,85 @@ export interface NotificationMethod {
 	name: string;
 	kind: string;
 	enabled: boolean;
-	config: {
-		WebHook: {
-			url: string;
-			method: string;
-			headers: Record<string, string>;
-			body: string;
-		};
-	};
+	template?: string | null; // Message template, replaces the default {notif_msg}
+	timeout?: number; // Seconds before a delivery attempt is given up
+	max_retries?: number; // Retries of failed deliveries, with exponential backoff
+	grouping?: boolean; // Notifications of the same alert check are sent as one message
+	digest_interval?: number | null; // Minutes notifications are collected for before being sent
+	config: NotificationConfig;
+}
+
+export type NotificationConfig =
+	| { WebHook: WebHookConfig }
+	| { Email: EmailConfig }
+	| { Exec: ExecConfig }
+	| { Mqtt: MqttConfig }
+	| { Slack: SlackConfig }
+	| { Discord: DiscordConfig }
+	| { Alertmanager: AlertmanagerConfig }
+	| { PagerDuty: PagerDutyConfig };
+
+export interface WebHookConfig {
+	url: string;
+	method: string;
+	headers: Record<string, string>;
+	body: string;
+}
+
+export interface EmailConfig {
+	server: string;
+	port: number;
+	security: 'starttls' | 'tls' | 'none';
+	username: string; // No authentication when empty
+	password: string;
+	from: string;
+	to: string[];
+	subject: string; // Template, a default subject when empty
+	body: string; // Template, the message when empty
+}
+
+export interface ExecConfig {
+	command: string; // Program to run, not through a shell
+	args: string[]; // Templates
+}
+
+export interface MqttConfig {
+	host: string;
+	port: number;
+	tls: boolean;
+	username: string; // No authentication when empty
+	password: string;
+	topic: string; // Template
+	payload: string; // Template, the variables as a JSON object when empty
+	qos: 0 | 1 | 2;
+	retain: boolean;
+}
+
+export interface SlackConfig {
+	url: string; // Slack or Mattermost incoming webhook
+}
+
+export interface DiscordConfig {
+	url: string;
+}
+
+export interface AlertmanagerConfig {
+	url: string; // Receiver of Alertmanager webhooks
+	headers: Record<string, string>;
+}
+
+export interface PagerDutyConfig {
+	routing_key: string; // Integration key of the service
+	severity: 'critical' | 'error' | 'warning' | 'info';
+	url: string; // Events API v2 endpoint
+}
+
+export interface NotificationTestResult {
+	success: boolean;
+	error?: string | null;
+	status?: number | null; // HTTP status or SMTP reply code
+	response?: string | null; // Start of the response body
+	latency_ms: number;
 }
 
 export interface AlertVar {
@@ -153,13 +227,65 @@ export interface AlertVar {
 
 export interface Alert {
 	id: string;
+	name?: string; // Generated from the condition when empty
 	var: AlertVar;
+	kind?: string; // "threshold", "rate" (change per hour) or "forecast"
 	threshold: number;
+	recovery_threshold?: number | null; // Threshold to cross back before resolving
+	horizon?: number | null; // Hours ahead checked by forecast alerts
 	operator: string;
 	enabled: boolean;
-	time_window: number;
+	time_window: number; // Minutes, fractions allowed
+	resolution?: 'second' | 'minute' | 'hour' | null; // Picked from the time window if unset
+	condition?: AlertCondition | null; // Replaces the single metric check when set
 	firing: boolean;
 	notif_methods: string[];
+	renotify_interval?: number | null; // Minutes between repeated notifications while firing
+	escalations?: AlertEscalation[]; // Ordered by delay
+	fired_at?: number | null;
+	last_notified?: number | null;
+	escalation_level?: number; // Number of escalations already notified
+	template?: string | null; // Message template, overrides the one of the methods
+}
+
+// Further notification methods to notify once an alert has been firing for a while
+export interface AlertEscalation {
+	after: number; // Minutes since the alert fired
+	notif_methods: string[];
+}
+
+// Boolean expression over metric checks, evaluated as a single alert
+export type AlertCondition =
+	| { op: 'and' | 'or'; conditions: AlertCondition[] }
+	| { op: 'not'; condition: AlertCondition }
+	| {
+			op: 'metric';
+			var: AlertVar;
+			kind?: string;
+			operator: string;
+			threshold: number;
+			horizon?: number | null;
+			time_window: number;
+			resolution?: 'second' | 'minute' | 'hour' | null;
+	  };
+
+// Time-bounded mute of the notifications of matching alerts
+export interface Silence {
+	id: string;
+	comment: string;
+	alert_ids: string[]; // Any alert if empty
+	cat?: string | null;
+	resrc?: string | null;
+	start_time: number;
+	end_time?: number | null; // Only open-ended when recurring
+	recurrence?: SilenceRecurrence | null;
+}
+
+// Weekly window within the bounds of a silence, in local time
+export interface SilenceRecurrence {
+	days: string[]; // "mon" to "sun"
+	start: string; // "HH:MM"
+	duration: number; // Minutes
 }
 
 export interface FileEntry {
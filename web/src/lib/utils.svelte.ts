@@ -37,13 +37,20 @@ export const types2names: { [key: string]: string } = {
 	total_write: 'Total Write',
 	disk_usage: 'Disk Usage',
 	rx: 'RX',
-	tx: 'TX'
+	tx: 'TX',
+	running: 'Running',
+	restarts: 'Restarts',
+	unhealthy: 'Unhealthy',
+	count: 'Count',
+	mem: 'Memory'
 };
 
 export const cat2names: { [key: string]: string } = {
 	sys: 'System',
 	net: 'Network',
-	disk: 'Storage'
+	disk: 'Storage',
+	docker: 'Docker',
+	proc: 'Processes'
 };
 
 export const var2unit: { [key: string]: string } = {
@@ -61,7 +68,12 @@ export const var2unit: { [key: string]: string } = {
 	total_write: 'B',
 	disk_usage: '%',
 	rx: 'B',
-	tx: 'B'
+	tx: 'B',
+	running: '',
+	restarts: '',
+	unhealthy: '',
+	count: '',
+	mem: 'B'
 };
 
 const paths = [
@@ -73,6 +85,7 @@ const paths = [
 	'/network',
 	'/notif_methods',
 	'/processes',
+	'/silences',
 	'/storage'
 ];
 